@@ -65,6 +65,30 @@ fn benchmark(c: &mut Criterion) {
     }
     group.finish();
 
+    let mut group = c.benchmark_group("merge");
+    group.throughput(Throughput::Elements(1));
+    for &cardinality in &cardinalities {
+        bench_merge::<CardinalityEstimatorMut>(&mut group, cardinality);
+        bench_merge::<CardinalityEstimatorSafeMut>(&mut group, cardinality);
+        bench_merge::<AmadeusStreamingEstimator>(&mut group, cardinality);
+        bench_merge::<ProbabilisticCollections>(&mut group, cardinality);
+        bench_merge::<HyperLogLog>(&mut group, cardinality);
+        bench_merge::<HyperLogLogPlus>(&mut group, cardinality);
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("merge_hll");
+    bench_merge_hll::<14, 4>(&mut group);
+    bench_merge_hll::<14, 6>(&mut group);
+    group.finish();
+
+    let mut group = c.benchmark_group("insert_all");
+    for &cardinality in &cardinalities {
+        group.throughput(Throughput::Elements(cardinality.max(1) as u64));
+        bench_insert_all(&mut group, cardinality);
+    }
+    group.finish();
+
     let results: Vec<StatRecord> = cardinalities
         .iter()
         .map(|&cardinality| StatRecord {
@@ -135,6 +159,35 @@ fn bench_insert<E: CardinalityEstimatorTrait<usize>>(
     );
 }
 
+/// Compare `Sketch::insert_all` against a naive loop of `Sketch::insert`
+fn bench_insert_all(group: &mut BenchmarkGroup<WallTime>, cardinality: usize) {
+    group.bench_with_input(
+        BenchmarkId::new("insert-loop", cardinality),
+        &cardinality,
+        |b, &cardinality| {
+            b.iter(|| {
+                let mut sketch = Sketch::<12, 6>::default();
+                for i in 0..black_box(cardinality) {
+                    sketch.insert(Element::from_hasher_default::<WyHash>(black_box(&i)));
+                }
+            });
+        },
+    );
+    group.bench_with_input(
+        BenchmarkId::new("insert-all", cardinality),
+        &cardinality,
+        |b, &cardinality| {
+            b.iter(|| {
+                let mut sketch = Sketch::<12, 6>::default();
+                sketch.insert_all(
+                    (0..black_box(cardinality))
+                        .map(|i| Element::from_hasher_default::<WyHash>(black_box(&i))),
+                );
+            });
+        },
+    );
+}
+
 fn bench_estimate<E: CardinalityEstimatorTrait<usize>>(
     group: &mut BenchmarkGroup<WallTime>,
     cardinality: usize,
@@ -152,6 +205,41 @@ fn bench_estimate<E: CardinalityEstimatorTrait<usize>>(
     );
 }
 
+fn bench_merge<E: CardinalityEstimatorTrait<usize>>(
+    group: &mut BenchmarkGroup<WallTime>,
+    cardinality: usize,
+) {
+    group.bench_with_input(
+        BenchmarkId::new(E::name(), cardinality),
+        &cardinality,
+        |b, &cardinality| {
+            let mut lhs = E::new();
+            let mut rhs = E::new();
+            for i in 0..black_box(cardinality) {
+                lhs.insert(black_box(&i));
+                rhs.insert(black_box(&(i + cardinality)));
+            }
+            b.iter(|| lhs.merge(black_box(&rhs)));
+        },
+    );
+}
+
+/// Merge two dense sketches, which exercises the vectorized merge with the `simd` feature and `W = 4`
+fn bench_merge_hll<const P: usize, const W: usize>(group: &mut BenchmarkGroup<WallTime>) {
+    let sketch = |range: std::ops::Range<usize>| -> Sketch<P, W> {
+        range.map(Element::from_hasher_default::<WyHash>).collect()
+    };
+    let lhs = sketch(0..100_000);
+    let rhs = sketch(50_000..150_000);
+    group.bench_function(format!("p{}w{}", P, W), |b| {
+        b.iter_batched_ref(
+            || lhs.clone(),
+            |lhs| lhs.merge(black_box(&rhs)),
+            criterion::BatchSize::SmallInput,
+        );
+    });
+}
+
 fn measure_allocations<E: CardinalityEstimatorTrait<usize>>(cardinality: usize) -> String {
     let _profiler = dhat::Profiler::builder().testing().build();
     let mut estimator = E::new();
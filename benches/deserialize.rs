@@ -0,0 +1,33 @@
+//! Compare checked `Sketch` and unchecked `TrustedSketch` deserialization of
+//! HyperLogLog sketches
+use cardinality_estimator_safe::{Element, Sketch, TrustedSketch};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use wyhash::WyHash;
+
+criterion_group!(benches, benchmark);
+criterion_main!(benches);
+
+fn benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("deserialize");
+    bench_deserialize::<12, 6>(&mut group);
+    bench_deserialize::<16, 6>(&mut group);
+    group.finish();
+}
+
+fn bench_deserialize<const P: usize, const W: usize>(
+    group: &mut criterion::BenchmarkGroup<criterion::measurement::WallTime>,
+) {
+    let sketch: Sketch<P, W> = (0..(1usize << P) * 10)
+        .map(Element::from_hasher_default::<WyHash>)
+        .collect();
+    let bytes = bincode::serialize(&sketch).unwrap();
+    let trusted_bytes = bincode::serialize(&TrustedSketch(sketch)).unwrap();
+    let params = format!("p{P}_w{W}");
+
+    group.bench_function(BenchmarkId::new("checked", &params), |b| {
+        b.iter(|| bincode::deserialize::<Sketch<P, W>>(black_box(&bytes)).unwrap())
+    });
+    group.bench_function(BenchmarkId::new("trusted", &params), |b| {
+        b.iter(|| bincode::deserialize::<TrustedSketch<P, W>>(black_box(&trusted_bytes)).unwrap())
+    });
+}
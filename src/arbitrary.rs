@@ -0,0 +1,116 @@
+//! # Arbitrary sketches for fuzzing
+//! With the `arbitrary` feature, `Sketch` and `Element` implement
+//! `arbitrary::Arbitrary`, so that fuzz harnesses of code holding sketches
+//! can generate them from the fuzzer's input.
+
+use crate::array::MAX_CAPACITY as ARRAY_MAX_CAPACITY;
+use crate::element::Element;
+use crate::sketch::Sketch;
+use crate::sparse::Sparse;
+use arbitrary::{Arbitrary, Result, Unstructured};
+
+impl<'a, const P: usize, const W: usize> Arbitrary<'a> for Element<P, W> {
+    /// An element with an arbitrary 64-bit hash (see `Element::from_hashed`)
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Element::from_hashed(u.arbitrary()?))
+    }
+
+    fn size_hint(depth: usize) -> (usize, Option<usize>) {
+        <u64 as Arbitrary>::size_hint(depth)
+    }
+}
+
+impl<'a, const P: usize, const W: usize> Arbitrary<'a> for Sketch<P, W> {
+    /// A sketch built by inserting elements, so it's always valid
+    ///
+    /// The input first picks a representation, then a number of inserts in
+    /// its range, so that every representation comes up even from short
+    /// inputs. Hll sketches get up to 8 times as many inserts as it takes to
+    /// reach them. The hashes are read from the input while it lasts, then
+    /// derived from an arbitrary seed.
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let sparse_max = if Sparse::<P, W>::ENABLED {
+            Sparse::<P, W>::MAX_LEN
+        } else {
+            ARRAY_MAX_CAPACITY
+        };
+        let inserts = match u.choose_index(4)? {
+            0 => u.int_in_range(0..=2)?,
+            1 => u.int_in_range(3..=ARRAY_MAX_CAPACITY)?,
+            2 if Sparse::<P, W>::ENABLED => u.int_in_range(ARRAY_MAX_CAPACITY + 1..=sparse_max)?,
+            _ => u.int_in_range(sparse_max + 1..=8 * sparse_max)?,
+        };
+        let mut seed: u64 = u.arbitrary()?;
+
+        let mut sketch = Sketch::default();
+        for _ in 0..inserts {
+            let hash = if u.is_empty() {
+                seed = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+                mix(seed)
+            } else {
+                u.arbitrary()?
+            };
+            sketch.insert(Element::from_hashed(hash));
+        }
+        Ok(sketch)
+    }
+}
+
+/// SplitMix64 finalizer, spreading consecutive seeds over all bits
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sketch::RepresentationKind;
+    use core::hash::BuildHasher;
+    use test_case::test_case;
+    use wyhash::WyHash;
+
+    /// Fuzz-like inputs: pseudo-random bytes of many lengths
+    fn inputs() -> impl Iterator<Item = Vec<u8>> {
+        let hasher = core::hash::BuildHasherDefault::<WyHash>::default();
+        (0..2_000u64).map(move |i| {
+            let len = (hasher.hash_one(i) % 256) as usize;
+            (0..len as u64)
+                .map(|j| hasher.hash_one((i, j)) as u8)
+                .collect()
+        })
+    }
+
+    #[test_case(Sketch::<8, 5>::default(); "p8 without sparse")]
+    #[test_case(Sketch::<12, 6>::default(); "p12")]
+    fn test_arbitrary_sketches<const P: usize, const W: usize>(_: Sketch<P, W>) {
+        let mut kinds = Vec::new();
+        let mut previous = Sketch::<P, W>::default();
+        for input in inputs() {
+            let mut u = Unstructured::new(&input);
+            let Ok(mut sketch) = Sketch::<P, W>::arbitrary(&mut u) else {
+                continue;
+            };
+            let element = Element::<P, W>::arbitrary(&mut u).unwrap();
+            if !kinds.contains(&sketch.representation_kind()) {
+                kinds.push(sketch.representation_kind());
+            }
+
+            // nothing panics on arbitrary sketches
+            let estimate = sketch.estimate();
+            assert_eq!(sketch.is_empty(), estimate == 0);
+            let mut merged = sketch.clone();
+            merged.merge(&previous);
+            assert!(merged.estimate() + 1 >= estimate.max(previous.estimate()) * 9 / 10);
+            assert_eq!(merged, sketch.union(&previous));
+            sketch.insert(element);
+            assert!(sketch.probably_contains(element));
+            previous = sketch;
+        }
+        // every representation comes up, but Sparse only where it's used
+        let expected = if Sparse::<P, W>::ENABLED { 4 } else { 3 };
+        assert_eq!(kinds.len(), expected, "{kinds:?}");
+        assert!(kinds.contains(&RepresentationKind::Hll));
+    }
+}
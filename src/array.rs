@@ -1,21 +1,47 @@
 //! ## Array representation
 //! Allows to estimate medium cardinality in [3..MAX_CAPACITY] range.
+//! Larger sketches move on to the `Sparse` representation, or straight to
+//! `HyperLogLog` for small `P` (see `Sparse::ENABLED`).
 
-use std::fmt::{Debug, Formatter};
-use std::mem::size_of_val;
-use std::ops::Deref;
+#[cfg(not(feature = "smallvec"))]
+use alloc::vec;
+#[cfg(any(test, feature = "with_serde", not(feature = "smallvec")))]
+use alloc::vec::Vec;
+use core::fmt::{Debug, Formatter};
+use core::hash::{Hash, Hasher};
+use core::mem::{size_of, size_of_val};
+use core::ops::Deref;
 
 use crate::hyperloglog::HyperLogLog;
 use crate::sketch::{Sketch, SketchTrait};
+use crate::sparse::Sparse;
 
 /// Maximum number of elements stored in array representation
 pub(crate) const MAX_CAPACITY: usize = 128;
 
+/// Backing storage of the array representation
+///
+/// With the `smallvec` feature, up to 16 items are stored inline instead of
+/// in a heap allocation, at the cost of a larger `Sketch` in every
+/// representation.
+#[cfg(not(feature = "smallvec"))]
+type Items = Vec<u32>;
+#[cfg(feature = "smallvec")]
+type Items = smallvec::SmallVec<[u32; 16]>;
+
 /// Array representation container
 #[derive(Clone)]
-pub(crate) struct Array<const P: usize, const W: usize>(Vec<u32>, usize);
+pub(crate) struct Array<const P: usize, const W: usize>(Items, usize);
 
 impl<const P: usize, const W: usize> Array<P, W> {
+    /// Move into an `Array` with other const parameters
+    ///
+    /// Caller is responsible for ensuring `(P2, W2) == (P, W)`
+    #[inline]
+    pub(crate) fn cast<const P2: usize, const W2: usize>(self) -> Array<P2, W2> {
+        Array(self.0, self.1)
+    }
+
     /// Insert encoded hash into `Array` representation
     /// Returns true on success, false otherwise.
     #[inline]
@@ -36,11 +62,16 @@ impl<const P: usize, const W: usize> Array<P, W> {
                     .expect("vec of len 4 can become array of len 4"),
                 h,
             ),
-            n => {
-                assert_eq!(n % 16, 0);
-                self.0.chunks_exact(16).any(|chunk| {
-                    contains_fixed_hopefully_vectorized::<16>(chunk.try_into().unwrap(), h)
-                })
+            n if n % 16 == 0 => self.0.chunks_exact(16).any(|chunk| {
+                contains_fixed_hopefully_vectorized::<16>(chunk.try_into().unwrap(), h)
+            }),
+            // only after `shrink_to_fit`
+            _ => {
+                let chunks = self.0.chunks_exact(16);
+                chunks.remainder().contains(&h)
+                    || chunks.into_iter().any(|chunk| {
+                        contains_fixed_hopefully_vectorized::<16>(chunk.try_into().unwrap(), h)
+                    })
             }
         };
 
@@ -56,28 +87,47 @@ impl<const P: usize, const W: usize> Array<P, W> {
             true
         } else if l < MAX_CAPACITY {
             // assert_eq!(l % 4, 0);
-            self.0.reserve_exact(l * 2);
-            self.0.resize(l * 2, 0);
+            // the next power of two is `l * 2`, unless shrunk by `shrink_to_fit`
+            let new_len = (l + 1).next_power_of_two();
+            self.0.reserve_exact(new_len);
+            self.0.resize(new_len, 0);
             self.0[l] = h;
-            self.1 = l - 1;
+            self.1 = new_len - l - 1;
             true
         } else {
             false
         }
     }
 
+    /// Drop the zero padding and any spare capacity of the backing storage
+    ///
+    /// The next insert of a new item grows it back to a power-of-two length.
+    #[inline]
+    pub(crate) fn shrink_to_fit(&mut self) {
+        self.0.truncate(self.0.len() - self.1);
+        self.0.shrink_to_fit();
+        self.1 = 0;
+    }
+
     /// Create new instance of `Array` representation from vector
     #[inline]
     pub(crate) fn from_small(a: u32, b: u32, c: u32) -> Array<P, W> {
-        Self(vec![a, b, c, 0], 1)
+        #[cfg(not(feature = "smallvec"))]
+        let items = vec![a, b, c, 0];
+        #[cfg(feature = "smallvec")]
+        let items = smallvec::smallvec![a, b, c, 0];
+        Self(items, 1)
     }
 
     /// Create a new instance from a raw vec of items (without trailing zero-padding)
     ///
     /// Caller is responsible for ensuring the vec lenght is >= 3 and <= MAX_CAPACITY
     #[inline]
-    #[cfg(feature = "with_serde")]
-    pub(crate) fn from_items(mut items: Vec<u32>) -> Array<P, W> {
+    #[cfg(any(test, feature = "with_serde"))]
+    pub(crate) fn from_items(items: Vec<u32>) -> Array<P, W> {
+        #[cfg(feature = "smallvec")]
+        let items = Items::from_vec(items);
+        let mut items = items;
         let alive = items.len();
         let mem_size = alive.next_power_of_two(); // as if this is already in stdlib
         let zeros = mem_size - alive;
@@ -93,11 +143,26 @@ impl<const P: usize, const W: usize> SketchTrait<P, W> for Array<P, W> {
     fn insert_encoded_hash(&mut self, h: u32) -> Option<Sketch<P, W>> {
         if self.insert(h) {
             None
+        } else if Sparse::<P, W>::ENABLED {
+            // upgrade from `Array` to `Sparse` representation
+            #[cfg(feature = "tracing")]
+            tracing::debug!(
+                p = P,
+                w = W,
+                cardinality = self.estimate_sketch() + 1,
+                "sketch upgraded from Array to Sparse"
+            );
+            Some(Sketch::Sparse(Sparse::from_array(self, h)))
         } else {
             // upgrade from `Array` to `HyperLogLog` representation
-            let mut hll = HyperLogLog::<P, W>::new(self);
-            hll.insert_encoded_hash(h);
-            Some(Sketch::Hll(hll))
+            #[cfg(feature = "tracing")]
+            tracing::debug!(
+                p = P,
+                w = W,
+                cardinality = self.estimate_sketch() + 1,
+                "sketch upgraded from Array to Hll"
+            );
+            Some(Sketch::Hll(HyperLogLog::promote(self, h)))
         }
     }
 
@@ -107,22 +172,59 @@ impl<const P: usize, const W: usize> SketchTrait<P, W> for Array<P, W> {
         self.0.len() - self.1
     }
 
+    /// `Array` representation always holds at least 3 items
+    #[inline]
+    fn is_empty_sketch(&self) -> bool {
+        false
+    }
+
     /// Return memory size of `Array` representation
     #[inline]
     fn size_of(&self) -> usize {
         size_of_val(self)
     }
+
+    /// Return memory size of `Array` representation, including its heap storage
+    #[inline]
+    fn heap_size(&self) -> usize {
+        #[cfg(feature = "smallvec")]
+        if !self.0.spilled() {
+            return size_of_val(self);
+        }
+        size_of_val(self) + self.0.capacity() * size_of::<u32>()
+    }
 }
 
 impl<const P: usize, const W: usize> Debug for Array<P, W> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         f.write_str(&self.to_string())
     }
 }
 
 impl<const P: usize, const W: usize> PartialEq for Array<P, W> {
+    /// Compare the stored items as sets, whatever order they were inserted in
     fn eq(&self, other: &Self) -> bool {
-        self.deref() == other.deref()
+        if self.deref() == other.deref() {
+            return true;
+        }
+        if self.len() != other.len() {
+            return false;
+        }
+        let (mut lhs, mut rhs) = (self.to_vec(), other.to_vec());
+        lhs.sort_unstable();
+        rhs.sort_unstable();
+        lhs == rhs
+    }
+}
+
+impl<const P: usize, const W: usize> Eq for Array<P, W> {}
+
+impl<const P: usize, const W: usize> Hash for Array<P, W> {
+    /// Hash the sorted stored items only, consistent with `PartialEq`
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let mut items = self.to_vec();
+        items.sort_unstable();
+        items.hash(state);
     }
 }
 
@@ -135,6 +237,11 @@ impl<const P: usize, const W: usize> Deref for Array<P, W> {
 }
 
 /// Vectorized linear fixed array search
+///
+/// The branchless scalar form autovectorizes: with `opt-level = 3` on x86_64
+/// it compiles to SSE2 `pcmpeqd` compares over the whole array. The `simd`
+/// feature uses portable SIMD explicitly instead of relying on the optimizer.
+#[cfg(not(feature = "simd"))]
 #[inline]
 fn contains_fixed_hopefully_vectorized<const N: usize>(a: [u32; N], v: u32) -> bool {
     let mut res = false;
@@ -144,12 +251,40 @@ fn contains_fixed_hopefully_vectorized<const N: usize>(a: [u32; N], v: u32) -> b
     res
 }
 
+/// Vectorized linear fixed array search, using portable SIMD
+#[cfg(feature = "simd")]
+#[inline]
+fn contains_fixed_hopefully_vectorized<const N: usize>(a: [u32; N], v: u32) -> bool {
+    use core::simd::cmp::SimdPartialEq;
+    use core::simd::Simd;
+
+    Simd::from_array(a).simd_eq(Simd::splat(v)).any()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use test_case::test_case;
 
     #[test]
     fn array_size() {
-        assert_eq!(std::mem::size_of::<Array<0, 0>>(), 32);
+        let expected = if cfg!(feature = "smallvec") { 80 } else { 32 };
+        assert_eq!(core::mem::size_of::<Array<0, 0>>(), expected);
+    }
+
+    #[test_case(3; "len 4")]
+    #[test_case(5; "len 8")]
+    #[test_case(16; "len 16")]
+    #[test_case(100; "len 128")]
+    fn test_insert_finds_duplicates(n: u32) {
+        let items: Vec<u32> = (1..=n).map(|i| i << 6 | 1).collect();
+        let mut arr = Array::<12, 6>::from_items(items.clone());
+        for &h in &items {
+            assert!(arr.insert(h));
+            assert_eq!(arr.estimate_sketch(), n as usize);
+        }
+        let extra = (n + 1) << 6 | 1;
+        assert!(arr.insert(extra));
+        assert_eq!(arr.estimate_sketch(), n as usize + 1);
     }
 }
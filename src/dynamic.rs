@@ -0,0 +1,353 @@
+//! ## Runtime-selected precision
+//! `Sketch` takes `P` and `W` as const generics, so they must be known at
+//! compile time. `DynSketch` wraps a `Sketch` for every supported `(P, W)`
+//! combination and dispatches at runtime, based on a `Precision` value that
+//! can come from configuration.
+
+use core::fmt;
+
+use crate::element::Element;
+use crate::sketch::Sketch;
+
+/// Error for `(P, W)` parameters outside the supported range
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParamError {
+    /// `P` is not in `[4..18]` range
+    Precision(usize),
+    /// `W` is not in `[4..6]` range
+    Width(usize),
+}
+
+impl fmt::Display for ParamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParamError::Precision(p) => write!(f, "precision P = {p} is not in [4..18] range"),
+            ParamError::Width(w) => write!(f, "register width W = {w} is not in [4..6] range"),
+        }
+    }
+}
+
+impl core::error::Error for ParamError {}
+
+/// Check that `(P, W)` parameters are supported
+///
+/// - `p`: precision in `[4..18]` range
+/// - `w`: register width in `[4..6]` range
+///
+/// This is the same check that `Element` applies to its const parameters at
+/// compile time.
+pub const fn validate_params(p: usize, w: usize) -> Result<(), ParamError> {
+    if p < 4 || p > 18 {
+        Err(ParamError::Precision(p))
+    } else if w < 4 || w > 6 {
+        Err(ParamError::Width(w))
+    } else {
+        Ok(())
+    }
+}
+
+/// Runtime `(P, W)` parameters for a `DynSketch`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Precision {
+    p: usize,
+    w: usize,
+}
+
+impl Precision {
+    /// Create new runtime parameters, if `p` and `w` are in supported range
+    ///
+    /// - `p`: precision in `[4..18]` range
+    /// - `w`: register width in `[4..6]` range
+    pub fn new(p: usize, w: usize) -> Option<Self> {
+        validate_params(p, w).ok().map(|()| Self { p, w })
+    }
+
+    /// Precision parameter `P`
+    pub fn p(&self) -> usize {
+        self.p
+    }
+
+    /// Register width parameter `W`
+    pub fn w(&self) -> usize {
+        self.w
+    }
+}
+
+impl Default for Precision {
+    /// Same parameters as the default `Sketch`
+    fn default() -> Self {
+        Self { p: 12, w: 6 }
+    }
+}
+
+/// Error merging sketches with different runtime parameters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeError {
+    /// The sketches were created with different precisions `P`
+    PrecisionMismatch { lhs: usize, rhs: usize },
+    /// The sketches have the same `P` but different register widths `W`
+    WidthMismatch { lhs: usize, rhs: usize },
+}
+
+impl fmt::Display for MergeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MergeError::PrecisionMismatch { lhs, rhs } => write!(
+                f,
+                "cannot merge sketch with precision P = {rhs} into sketch with P = {lhs}"
+            ),
+            MergeError::WidthMismatch { lhs, rhs } => write!(
+                f,
+                "cannot merge sketch with register width W = {rhs} into sketch with W = {lhs}"
+            ),
+        }
+    }
+}
+
+impl core::error::Error for MergeError {}
+
+macro_rules! dyn_sketch {
+    ($($variant:ident => ($p:literal, $w:literal),)*) => {
+        /// A `Sketch` with `(P, W)` parameters selected at runtime
+        ///
+        /// Since `Element` encoding depends on `P` and `W`, elements are
+        /// inserted as already-hashed `u64` values (see `Element::from_hashed`).
+        #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+        pub enum DynSketch {
+            $($variant(Sketch<$p, $w>),)*
+        }
+
+        impl DynSketch {
+            /// Create an empty sketch with the given parameters
+            pub fn new(precision: Precision) -> Self {
+                match (precision.p, precision.w) {
+                    $(($p, $w) => DynSketch::$variant(Sketch::default()),)*
+                    _ => unreachable!("`Precision` is always in supported range"),
+                }
+            }
+
+            /// Wrap a statically-typed `Sketch`, or `None` if its `(P, W)`
+            /// parameters are not supported
+            pub fn from_sketch<const P: usize, const W: usize>(sketch: Sketch<P, W>) -> Option<Self> {
+                match (P, W) {
+                    $(($p, $w) => Some(DynSketch::$variant(sketch.cast())),)*
+                    _ => None,
+                }
+            }
+
+            /// Unwrap the statically-typed `Sketch`, or give back `self` if
+            /// its parameters are not `(P, W)`
+            pub fn into_sketch<const P: usize, const W: usize>(self) -> Result<Sketch<P, W>, Self> {
+                match self {
+                    $(DynSketch::$variant(sketch) if (P, W) == ($p, $w) => Ok(sketch.cast()),)*
+                    other => Err(other),
+                }
+            }
+
+            /// The `(P, W)` parameters of this sketch
+            pub fn precision(&self) -> Precision {
+                match self {
+                    $(DynSketch::$variant(_) => Precision { p: $p, w: $w },)*
+                }
+            }
+
+            /// Insert an already-hashed set member to count
+            pub fn insert_hashed(&mut self, hashed: u64) {
+                match self {
+                    $(DynSketch::$variant(sketch) => sketch.insert(Element::from_hashed(hashed)),)*
+                }
+            }
+
+            /// Compute the current estimated cardinality
+            pub fn estimate(&self) -> usize {
+                match self {
+                    $(DynSketch::$variant(sketch) => sketch.estimate(),)*
+                }
+            }
+
+            /// Compute the current estimated cardinality without rounding it
+            ///
+            /// See `Sketch::estimate_f64`.
+            pub fn estimate_f64(&self) -> f64 {
+                match self {
+                    $(DynSketch::$variant(sketch) => sketch.estimate_f64(),)*
+                }
+            }
+
+            /// Merge another sketch into this one
+            ///
+            /// Fails without modifying this sketch if the parameters differ,
+            /// reporting a precision mismatch first if both differ.
+            pub fn try_merge(&mut self, rhs: &Self) -> Result<(), MergeError> {
+                match (self, rhs) {
+                    $((DynSketch::$variant(lhs), DynSketch::$variant(rhs)) => {
+                        lhs.merge(rhs);
+                        Ok(())
+                    })*
+                    (lhs, rhs) => {
+                        let (lhs, rhs) = (lhs.precision(), rhs.precision());
+                        Err(if lhs.p != rhs.p {
+                            MergeError::PrecisionMismatch { lhs: lhs.p, rhs: rhs.p }
+                        } else {
+                            MergeError::WidthMismatch { lhs: lhs.w, rhs: rhs.w }
+                        })
+                    }
+                }
+            }
+        }
+    };
+}
+
+dyn_sketch! {
+    P4W4 => (4, 4),
+    P4W5 => (4, 5),
+    P4W6 => (4, 6),
+    P5W4 => (5, 4),
+    P5W5 => (5, 5),
+    P5W6 => (5, 6),
+    P6W4 => (6, 4),
+    P6W5 => (6, 5),
+    P6W6 => (6, 6),
+    P7W4 => (7, 4),
+    P7W5 => (7, 5),
+    P7W6 => (7, 6),
+    P8W4 => (8, 4),
+    P8W5 => (8, 5),
+    P8W6 => (8, 6),
+    P9W4 => (9, 4),
+    P9W5 => (9, 5),
+    P9W6 => (9, 6),
+    P10W4 => (10, 4),
+    P10W5 => (10, 5),
+    P10W6 => (10, 6),
+    P11W4 => (11, 4),
+    P11W5 => (11, 5),
+    P11W6 => (11, 6),
+    P12W4 => (12, 4),
+    P12W5 => (12, 5),
+    P12W6 => (12, 6),
+    P13W4 => (13, 4),
+    P13W5 => (13, 5),
+    P13W6 => (13, 6),
+    P14W4 => (14, 4),
+    P14W5 => (14, 5),
+    P14W6 => (14, 6),
+    P15W4 => (15, 4),
+    P15W5 => (15, 5),
+    P15W6 => (15, 6),
+    P16W4 => (16, 4),
+    P16W5 => (16, 5),
+    P16W6 => (16, 6),
+    P17W4 => (17, 4),
+    P17W5 => (17, 5),
+    P17W6 => (17, 6),
+    P18W4 => (18, 4),
+    P18W5 => (18, 5),
+    P18W6 => (18, 6),
+}
+
+impl Default for DynSketch {
+    /// Initialize an empty sketch with default parameters
+    fn default() -> Self {
+        DynSketch::new(Precision::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::hash::BuildHasher;
+    use std::hash::BuildHasherDefault;
+    use test_case::test_case;
+    use wyhash::WyHash;
+
+    fn hashed(i: usize) -> u64 {
+        BuildHasherDefault::<WyHash>::default().hash_one(i)
+    }
+
+    #[test_case(3, 6 => None)]
+    #[test_case(19, 6 => None)]
+    #[test_case(12, 3 => None)]
+    #[test_case(12, 7 => None)]
+    #[test_case(4, 4 => Some((4, 4)))]
+    #[test_case(18, 6 => Some((18, 6)))]
+    fn test_precision(p: usize, w: usize) -> Option<(usize, usize)> {
+        Precision::new(p, w).map(|precision| (precision.p(), precision.w()))
+    }
+
+    #[test_case(3, 6 => Err(ParamError::Precision(3)))]
+    #[test_case(4, 6 => Ok(()))]
+    #[test_case(18, 6 => Ok(()))]
+    #[test_case(19, 6 => Err(ParamError::Precision(19)))]
+    #[test_case(12, 3 => Err(ParamError::Width(3)))]
+    #[test_case(12, 4 => Ok(()))]
+    #[test_case(12, 6 => Ok(()))]
+    #[test_case(12, 7 => Err(ParamError::Width(7)))]
+    fn test_validate_params(p: usize, w: usize) -> Result<(), ParamError> {
+        validate_params(p, w)
+    }
+
+    #[test_case(10, 5; "p10 w5")]
+    #[test_case(14, 6; "p14 w6")]
+    fn test_runtime_precision(p: usize, w: usize) {
+        let precision = Precision::new(p, w).unwrap();
+        let mut lhs = DynSketch::new(precision);
+        let mut rhs = DynSketch::new(precision);
+        assert_eq!(lhs.precision(), precision);
+        for i in 0..10_000 {
+            lhs.insert_hashed(hashed(i));
+            rhs.insert_hashed(hashed(i + 5_000));
+        }
+        lhs.try_merge(&rhs).unwrap();
+
+        let standard_error = 1.04 / 2f64.powi(p as i32).sqrt();
+        let relative_error = lhs.estimate().abs_diff(15_000) as f64 / 15_000.0;
+        assert!(relative_error < 3.0 * standard_error);
+    }
+
+    #[test]
+    fn test_matches_static_sketch() {
+        let mut dynamic = DynSketch::new(Precision::new(14, 6).unwrap());
+        let mut fixed = Sketch::<14, 6>::default();
+        for i in 0..1_000 {
+            dynamic.insert_hashed(hashed(i));
+            fixed.insert(Element::from_hashed(hashed(i)));
+        }
+        assert_eq!(dynamic, DynSketch::P14W6(fixed));
+    }
+
+    #[test_case(0; "empty")]
+    #[test_case(2; "small")]
+    #[test_case(100; "array")]
+    #[test_case(10_000; "hll")]
+    fn test_sketch_conversion(n: usize) {
+        let sketch: Sketch<10, 5> = (0..n).map(|i| Element::from_hashed(hashed(i))).collect();
+        let estimate = sketch.estimate();
+
+        let dynamic = DynSketch::from_sketch(sketch.clone()).unwrap();
+        assert_eq!(dynamic.precision(), Precision::new(10, 5).unwrap());
+        assert_eq!(dynamic.estimate(), estimate);
+
+        let dynamic = dynamic.into_sketch::<10, 6>().unwrap_err();
+        let round_trip = dynamic.into_sketch::<10, 5>().unwrap();
+        assert_eq!(round_trip, sketch);
+        assert_eq!(round_trip.estimate(), estimate);
+
+        assert_eq!(DynSketch::from_sketch(Sketch::<3, 6>::default()), None);
+    }
+
+    #[test_case((10, 6), (14, 6) => MergeError::PrecisionMismatch { lhs: 10, rhs: 14 }; "precision")]
+    #[test_case((14, 6), (14, 4) => MergeError::WidthMismatch { lhs: 6, rhs: 4 }; "width")]
+    #[test_case((10, 4), (14, 6) => MergeError::PrecisionMismatch { lhs: 10, rhs: 14 }; "both")]
+    fn test_merge_mismatch(lhs: (usize, usize), rhs: (usize, usize)) -> MergeError {
+        let mut lhs = DynSketch::new(Precision::new(lhs.0, lhs.1).unwrap());
+        lhs.insert_hashed(hashed(1));
+        let mut rhs = DynSketch::new(Precision::new(rhs.0, rhs.1).unwrap());
+        rhs.insert_hashed(hashed(2));
+
+        let before = lhs.clone();
+        let err = lhs.try_merge(&rhs).unwrap_err();
+        assert_eq!(lhs, before);
+        err
+    }
+}
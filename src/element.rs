@@ -1,6 +1,9 @@
+use alloc::vec::Vec;
+use core::hash::{BuildHasher, BuildHasherDefault, Hash, Hasher};
+
+use crate::dynamic::validate_params;
 #[cfg(feature = "with_digest")]
 use digest::Digest;
-use std::hash::{BuildHasher, BuildHasherDefault, Hash, Hasher};
 
 /// A member that can be inserted into a Sketch
 ///
@@ -13,7 +16,7 @@ pub struct Element<const P: usize = 12, const W: usize = 6>(pub(crate) u32);
 impl<const P: usize, const W: usize> Element<P, W> {
     /// Wrap an already-hashed element for insertion
     ///
-    /// For advanced use cases: if your input value is not `std::hash::Hash` or
+    /// For advanced use cases: if your input value is not `core::hash::Hash` or
     /// you want to use some other hash function not supported by the other
     /// initialisers, you can hash the elements yourself and use this.
     ///
@@ -22,14 +25,54 @@ impl<const P: usize, const W: usize> Element<P, W> {
     #[inline]
     pub fn from_hashed(hashed: u64) -> Self {
         // Ensure that `P` and `W` are in correct range at compile time
-        const { assert!(P >= 4 && P <= 18 && W >= 4 && W <= 6) }
+        const {
+            assert!(
+                validate_params(P, W).is_ok(),
+                "`P` must be in [4..18] range and `W` in [4..6] range"
+            )
+        }
         let idx = (hashed as u32) & ((1 << (32 - W - 1)) - 1);
-        let rank = (!hashed >> P).trailing_zeros() + 1;
+        // clamp to the largest rank a `W`-bit register holds, so it can't
+        // overflow into `idx`
+        let rank = ((!hashed >> P).trailing_zeros() + 1).min((1 << W) - 1);
         Self((idx << W) | rank)
     }
 
+    /// Wrap a hash already encoded for this element's `P` and `W`
+    ///
+    /// For advanced use cases, like re-inserting encoded hashes persisted from
+    /// a previous run without the original items. The encoding is specific to
+    /// `P` and `W`: encoded hashes from other parameters will silently produce
+    /// wrong estimates.
+    ///
+    /// # Panics
+    ///
+    /// If the rank (the low `W` bits) is zero, which is never a valid encoded
+    /// hash. `Sketch::from_encoded_hashes` skips these instead.
+    #[inline]
+    pub fn from_encoded(encoded: u32) -> Self {
+        assert!(
+            encoded & ((1 << W) - 1) != 0,
+            "encoded hash must have a non-zero rank"
+        );
+        Self(encoded)
+    }
+
+    /// The hash encoded for this element's `P` and `W`
+    ///
+    /// For storing elements outside of a sketch, to replay them later with
+    /// `from_encoded` without the original items.
+    #[inline]
+    pub fn encoded(&self) -> u32 {
+        self.0
+    }
+
     /// Wrap a `Hash` element with a `BuildHasher` instance
     ///
+    /// The `BuildHasher` can carry a runtime seed, for example to give each
+    /// tenant's sketches a different hash function. Sketches built with
+    /// different seeds must never be merged.
+    ///
     /// The `BuildHasher` can initialize state for secret/salting, but if you
     /// need that, consider enabling the `use_digest` feature and using a secure
     /// hash with `from_digest_with_prefix`.
@@ -44,6 +87,48 @@ impl<const P: usize, const W: usize> Element<P, W> {
         Self::from_hasher(element, BuildHasherDefault::<H>::default())
     }
 
+    /// Wrap many `Hash` elements with a `Hasher` specified by type
+    ///
+    /// Same as `from_hasher_default` for each item. See
+    /// `from_hasher_default_iter` to avoid collecting them.
+    pub fn from_hasher_default_all<H: Hasher + Default, I>(items: I) -> Vec<Self>
+    where
+        I: IntoIterator,
+        I::Item: Hash,
+    {
+        Self::from_hasher_default_iter::<H, I>(items).collect()
+    }
+
+    /// Lazily wrap many `Hash` elements with a `Hasher` specified by type
+    pub fn from_hasher_default_iter<H: Hasher + Default, I>(items: I) -> impl Iterator<Item = Self>
+    where
+        I: IntoIterator,
+        I::Item: Hash,
+    {
+        items.into_iter().map(Self::from_hasher_default::<H>)
+    }
+
+    /// Wrap raw element bytes with a `BuildHasher` instance
+    ///
+    /// The bytes are fed straight into the hasher with `Hasher::write`. This
+    /// differs from `from_hasher` with a `&[u8]` element, where `Hash` also
+    /// writes a length prefix, so the two produce different elements for the
+    /// same bytes: don't mix them in one sketch.
+    #[inline]
+    pub fn from_bytes(bytes: impl AsRef<[u8]>, hasher: impl BuildHasher) -> Self {
+        let mut hasher = hasher.build_hasher();
+        hasher.write(bytes.as_ref());
+        Self::from_hashed(hasher.finish())
+    }
+
+    /// Wrap raw element bytes with a `Hasher` specified by type
+    ///
+    /// See `from_bytes`.
+    #[inline]
+    pub fn from_bytes_default<H: Hasher + Default>(bytes: impl AsRef<[u8]>) -> Self {
+        Self::from_bytes(bytes, BuildHasherDefault::<H>::default())
+    }
+
     /// Wrap element bytes with a secret prefix hashed by any `Digest` hasher
     ///
     /// This can help resist offline attacks against your estimates if a user
@@ -60,6 +145,13 @@ impl<const P: usize, const W: usize> Element<P, W> {
     ) -> Self {
         let mut hasher = D::new_with_prefix(prefix);
         hasher.update(element);
+        Self::from_digest_output(hasher)
+    }
+
+    /// Wrap the output of a `Digest` hasher that has absorbed the element
+    #[cfg(feature = "with_digest")]
+    #[inline]
+    fn from_digest_output<D: Digest>(hasher: D) -> Self {
         let first8: [u8; 8] = hasher // TODO: there's def a better way to split the first 8 from GenericArray with type checking
             .finalize()
             .as_slice()
@@ -84,6 +176,49 @@ impl<const P: usize, const W: usize> Element<P, W> {
     }
 }
 
+/// A `Digest` hasher that has already absorbed a secret prefix
+///
+/// Makes the same elements as `Element::from_digest_with_prefix`, but hashes
+/// the prefix only once: each element clones the prefixed state instead. This
+/// saves a compression of the prefix per element for block hashes like SHA-256.
+///
+/// ```
+/// use cardinality_estimator_safe::{DigestPrefixHasher, Element, Sketch};
+/// use sha2::Sha256;
+///
+/// let hasher = DigestPrefixHasher::<Sha256>::new(b"secret");
+/// let element: Element = hasher.element(b"thing");
+/// assert_eq!(
+///     element,
+///     Element::from_digest_with_prefix::<Sha256>(b"secret", b"thing"),
+/// );
+///
+/// let mut sketch: Sketch = Sketch::default();
+/// sketch.insert(element);
+/// ```
+#[cfg(feature = "with_digest")]
+#[derive(Debug, Clone)]
+pub struct DigestPrefixHasher<D>(D);
+
+#[cfg(feature = "with_digest")]
+impl<D: Digest + Clone> DigestPrefixHasher<D> {
+    /// Absorb the secret prefix
+    pub fn new(prefix: impl AsRef<[u8]>) -> Self {
+        Self(D::new_with_prefix(prefix))
+    }
+
+    /// Wrap element bytes, hashed after the prefix
+    #[inline]
+    pub fn element<const P: usize, const W: usize>(
+        &self,
+        element: impl AsRef<[u8]>,
+    ) -> Element<P, W> {
+        let mut hasher = self.0.clone();
+        hasher.update(element);
+        Element::from_digest_output(hasher)
+    }
+}
+
 #[cfg(test)]
 pub mod tests {
     use super::*;
@@ -94,10 +229,128 @@ pub mod tests {
         let _: Element = Element::from_hasher_default::<WyHash>(&123);
     }
 
+    #[test]
+    fn test_from_hasher_default_all() {
+        use crate::Sketch;
+
+        let elements: Vec<Element> = Element::from_hasher_default_all::<WyHash, _>(0..1000usize);
+        assert_eq!(elements.len(), 1000);
+        assert_eq!(elements[7], Element::from_hasher_default::<WyHash>(7usize));
+
+        let mut direct = Sketch::default();
+        for i in 0..1000usize {
+            direct.insert(Element::from_hasher_default::<WyHash>(i));
+        }
+        let batch: Sketch = elements.into_iter().collect();
+        assert_eq!(batch, direct);
+        assert_eq!(batch.estimate(), direct.estimate());
+
+        let lazy: Sketch = Element::from_hasher_default_iter::<WyHash, _>(0..1000usize).collect();
+        assert_eq!(lazy, direct);
+    }
+
+    struct SeededWyHash(u64);
+
+    impl BuildHasher for SeededWyHash {
+        type Hasher = WyHash;
+
+        fn build_hasher(&self) -> WyHash {
+            WyHash::with_seed(self.0)
+        }
+    }
+
+    #[test]
+    fn test_from_hasher_seeded() {
+        let a: Element = Element::from_hasher("tenant data", SeededWyHash(1));
+        let b: Element = Element::from_hasher("tenant data", SeededWyHash(2));
+        assert_ne!(a, b);
+        assert_eq!(a, Element::from_hasher("tenant data", SeededWyHash(1)));
+        assert_eq!(
+            Element::<12, 6>::from_hasher("tenant data", SeededWyHash(0)),
+            Element::from_hasher_default::<WyHash>("tenant data"),
+        );
+    }
+
+    #[test]
+    fn test_from_bytes() {
+        let mut hasher = WyHash::default();
+        hasher.write(b"abc");
+        let manual: Element = Element::from_hashed(hasher.finish());
+
+        assert_eq!(Element::from_bytes_default::<WyHash>(b"abc"), manual);
+        assert_eq!(Element::from_bytes(b"abc", SeededWyHash(0)), manual);
+        // `Hash` for slices adds a length prefix
+        assert_ne!(Element::from_hasher_default::<WyHash>(&b"abc"[..]), manual);
+    }
+
+    #[test]
+    fn test_from_encoded() {
+        use crate::Sketch;
+
+        let hashed: Vec<Element> = (0..1_000usize)
+            .map(Element::from_hasher_default::<WyHash>)
+            .collect();
+        let encoded: Vec<Element> = hashed
+            .iter()
+            .map(|e| Element::from_encoded(e.encoded()))
+            .collect();
+        assert_eq!(hashed, encoded);
+
+        for n in [1, 2, 100, 1_000] {
+            let lhs: Sketch = hashed[..n].iter().copied().collect();
+            let rhs: Sketch = encoded[..n].iter().copied().collect();
+            assert_eq!(lhs, rhs);
+        }
+    }
+
+    #[test]
+    fn test_encoded() {
+        let e = Element::<12, 6>::from_hashed(0x0123_4567_89ab_cdef);
+        // idx from the low 25 bits, rank from the trailing ones above the low
+        // 12 bits (none in 0xc), plus one
+        assert_eq!(e.encoded(), (0x01ab_cdef << 6) | 1);
+        assert_eq!(Element::from_encoded(e.encoded()), e);
+    }
+
+    #[test]
+    fn test_rank_clamped_to_width() {
+        // all ones above the low 12 bits: a rank of 65, too wide even for `W = 6`
+        for hashed in [!0xfff | 0xabc, !0] {
+            let e = Element::<12, 4>::from_hashed(hashed);
+            assert_eq!(e.encoded() & 0xf, 15);
+            assert_eq!(e.encoded() >> 4, (hashed as u32) & ((1 << 27) - 1));
+
+            let e = Element::<12, 6>::from_hashed(hashed);
+            assert_eq!(e.encoded() & 0x3f, 63);
+            assert_eq!(e.encoded() >> 6, (hashed as u32) & ((1 << 25) - 1));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "non-zero rank")]
+    fn test_from_encoded_zero_rank() {
+        Element::<12, 6>::from_encoded(1 << 6);
+    }
+
     #[cfg(feature = "with_digest")]
     #[test]
     fn test_bleh() {
         use sha2::Sha256;
         let _: Element = Element::from_digest_oneshot::<Sha256>(&[123]);
     }
+
+    #[cfg(feature = "with_digest")]
+    #[test]
+    fn test_digest_prefix_hasher() {
+        use sha2::Sha256;
+        // prefixes shorter and longer than a SHA-256 block
+        for prefix in [&b""[..], b"secret", &[7; 100]] {
+            let hasher = DigestPrefixHasher::<Sha256>::new(prefix);
+            for i in 0..100u32 {
+                let bytes = i.to_le_bytes();
+                let e: Element = hasher.element(bytes);
+                assert_eq!(e, Element::from_digest_with_prefix::<Sha256>(prefix, bytes));
+            }
+        }
+    }
 }
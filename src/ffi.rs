@@ -0,0 +1,119 @@
+//! # C bindings
+//! With the `ffi` feature, `extern "C"` functions expose a default `Sketch`
+//! to C and C++ through an opaque pointer:
+//!
+//! ```c
+//! typedef struct CeSketch CeSketch;
+//!
+//! CeSketch *ce_new(void);
+//! void ce_insert_hash(CeSketch *sketch, uint64_t hash);
+//! uint64_t ce_estimate(const CeSketch *sketch);
+//! void ce_merge(CeSketch *dst, const CeSketch *src);
+//! uint8_t *ce_serialize(const CeSketch *sketch, size_t *out_len);
+//! void ce_bytes_free(uint8_t *bytes, size_t len);
+//! void ce_free(CeSketch *sketch);
+//! ```
+//!
+//! ## Ownership
+//! - `ce_new` returns a sketch owned by the caller, who must release it with
+//!   `ce_free` exactly once, and not use it afterwards
+//! - the other functions borrow the sketch for the duration of the call only
+//! - `ce_serialize` returns bytes owned by the caller, independent of the
+//!   sketch, who must release them with `ce_bytes_free` exactly once, passing
+//!   the length written to `*out_len`, and not use them afterwards
+//! - `ce_merge` must be given two different sketches
+//! - a null sketch is ignored: nothing is inserted or merged, the estimate is
+//!   0, and `ce_serialize` returns null with `*out_len` set to 0; freeing null
+//!   bytes does nothing
+//!
+//! Sketches aren't synchronized: a sketch may move between threads, but calls
+//! using it must not overlap while any of them takes it mutably.
+//!
+//! The functions take `Box` and `Option<&T>` parameters, which Rust guarantees
+//! to be passed like C pointers, with `None` for null. This is the only module
+//! of the crate with `unsafe` code: exporting unmangled symbols with
+//! `#[no_mangle]` is unsafe, since they may clash with other symbols at link
+//! time, and `ce_bytes_free` rebuilds the serialized bytes from a raw pointer
+//! and length. The contract above can't be checked here: breaking it is
+//! undefined behavior, like with any C library.
+
+use crate::element::Element;
+use crate::serde::CompactSketch;
+use crate::sketch::Sketch;
+use alloc::boxed::Box;
+
+/// A default `Sketch` behind an opaque pointer for C callers
+#[derive(Debug, Clone, Default)]
+pub struct CeSketch {
+    sketch: Sketch,
+}
+
+/// Create an empty sketch, to be released with `ce_free`
+#[no_mangle]
+pub extern "C" fn ce_new() -> Box<CeSketch> {
+    Box::default()
+}
+
+/// Insert an element by its 64-bit hash (see `Element::from_hashed`)
+#[no_mangle]
+pub extern "C" fn ce_insert_hash(sketch: Option<&mut CeSketch>, hash: u64) {
+    if let Some(sketch) = sketch {
+        sketch.sketch.insert(Element::from_hashed(hash));
+    }
+}
+
+/// Estimate the number of distinct elements inserted
+#[no_mangle]
+pub extern "C" fn ce_estimate(sketch: Option<&CeSketch>) -> u64 {
+    sketch.map_or(0, |sketch| sketch.sketch.estimate() as u64)
+}
+
+/// Merge `src` into `dst`, leaving `src` unchanged
+#[no_mangle]
+pub extern "C" fn ce_merge(dst: Option<&mut CeSketch>, src: Option<&CeSketch>) {
+    if let (Some(dst), Some(src)) = (dst, src) {
+        dst.sketch.merge(&src.sketch);
+    }
+}
+
+/// Serialize as the `postcard` serialization of a `CompactSketch`, writing
+/// its length to `out_len`
+///
+/// The bytes are owned by the caller, to be released with `ce_bytes_free`.
+#[no_mangle]
+pub extern "C" fn ce_serialize(sketch: Option<&CeSketch>, out_len: Option<&mut usize>) -> *mut u8 {
+    let Some(sketch) = sketch else {
+        if let Some(out_len) = out_len {
+            *out_len = 0;
+        }
+        return core::ptr::null_mut();
+    };
+    let bytes = postcard::to_allocvec(&CompactSketch::from(sketch.sketch.clone()))
+        .expect("serializing to a vec can't fail");
+    if let Some(out_len) = out_len {
+        *out_len = bytes.len();
+    }
+    Box::into_raw(bytes.into_boxed_slice()).cast()
+}
+
+/// Release bytes returned by `ce_serialize`
+///
+/// # Safety
+///
+/// `bytes` must be null, or returned by `ce_serialize` with `len` written to
+/// its `out_len`, and not released before.
+#[no_mangle]
+pub unsafe extern "C" fn ce_bytes_free(bytes: *mut u8, len: usize) {
+    if bytes.is_null() {
+        return;
+    }
+    // SAFETY: `ce_serialize` leaked a `Box<[u8]>` of `len` bytes at `bytes`,
+    // which the caller hands back exactly once
+    drop(unsafe { Box::from_raw(core::ptr::slice_from_raw_parts_mut(bytes, len)) });
+}
+
+/// Release a sketch created by `ce_new`
+#[no_mangle]
+pub extern "C" fn ce_free(sketch: Option<Box<CeSketch>>) {
+    drop(sketch);
+}
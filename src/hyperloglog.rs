@@ -4,32 +4,111 @@
 //!
 //! [Original HyperLogLog++ paper](https://static.googleusercontent.com/media/research.google.com/en//pubs/archive/40671.pdf)
 
-use std::fmt::{Debug, Formatter};
-use std::mem::size_of_val;
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::{Debug, Formatter};
+use core::hash::{Hash, Hasher};
+use core::mem::{size_of, size_of_val};
 
+#[cfg(feature = "minhash")]
+use crate::minhash::MinHash;
 use crate::sketch::{Sketch, SketchTrait};
+#[cfg(feature = "theta")]
+use crate::theta::Kmv;
 
 #[derive(Clone)]
 pub(crate) struct HyperLogLog<const P: usize = 12, const W: usize = 6> {
-    pub(crate) zeros: u32,
-    pub(crate) harmonic_sum: f32,
+    /// Number of registers at each of the `2^W` ranks, updated with every
+    /// register (see `update_stats`), so estimates don't scan the registers
+    pub(crate) histogram: Box<[u32]>,
     pub(crate) registers: Vec<u32>,
+    /// Smallest encoded hashes, unknown after merging registers from outside
+    /// a sketch (see `theta_intersection`)
+    #[cfg(feature = "theta")]
+    pub(crate) kmv: Option<Kmv>,
+    /// MinHash bins, unknown after merging registers from outside a sketch
+    /// (see `minhash_signature`)
+    #[cfg(feature = "minhash")]
+    pub(crate) minhash: Option<MinHash>,
 }
 
 impl<const P: usize, const W: usize> HyperLogLog<P, W> {
     /// Number of HyperLogLog registers
-    const M: usize = 1 << P;
+    pub(crate) const M: usize = 1 << P;
     /// HyperLogLog representation `u32` slice length based on #registers, stored zero registers, harmonic sum, and
     /// one extra element for branchless register updates (see `set_register` for more details).
     pub(crate) const HLL_SLICE_LEN: usize = Self::M * W / 32 + 3;
+    /// Index of the spare `registers` element holding the promotion count (see
+    /// `promoted_count`). Register updates never reach it.
+    pub(crate) const COUNT_IDX: usize = Self::HLL_SLICE_LEN - 1;
+    /// Flag of the promotion count element set while the count is exact
+    const EXACT_COUNT: u32 = 1 << 31;
+    /// Largest promotion count: a full Sparse representation (or Array, when
+    /// Sparse is disabled) and the element that overflowed it
+    #[cfg(feature = "with_serde")]
+    pub(crate) const MAX_PROMOTED_COUNT: u32 = if crate::sparse::Sparse::<P, W>::ENABLED {
+        crate::sparse::Sparse::<P, W>::MAX_LEN as u32 + 1
+    } else {
+        crate::array::MAX_CAPACITY as u32 + 1
+    };
+    /// Index of the spare `registers` element, always zero. Register updates
+    /// never reach it either: at most they rewrite its bits with themselves.
+    const SPARE_IDX: usize = Self::HLL_SLICE_LEN - 2;
+    /// Fixed-point scale of the harmonic sum: with `2^SUM_SHIFT` as one, every
+    /// `1/2^rank` term of a `W`-bit rank is an integer, so the sum is exact
+    const SUM_SHIFT: usize = (1 << W) - 1;
+    /// Highest rank a register can hold, limited by hash bits and register width
+    pub(crate) const MAX_RANK: usize = if 64 - P + 1 < (1 << W) - 1 {
+        64 - P + 1
+    } else {
+        (1 << W) - 1
+    };
+    /// Estimate above which at least 1% of registers are expected to hold
+    /// `MAX_RANK`, so the LogLog-Beta estimate starts to degrade.
+    ///
+    /// A register is saturated with probability `1 - exp(-n/M/2^(MAX_RANK-1))`,
+    /// which is about 1% at `n = M * 2^(MAX_RANK-1) / 100`.
+    pub(crate) const SATURATION_THRESHOLD: f64 =
+        (Self::M as f64) * ((1u64 << (Self::MAX_RANK - 1)) as f64) / 100.0;
+
+    /// Relative standard error of HyperLogLog estimates: `1.04 / sqrt(M)`
+    #[inline]
+    pub(crate) fn relative_standard_error() -> f64 {
+        1.04 / sqrt(Self::M as f64)
+    }
+
+    /// Move into a `HyperLogLog` with other const parameters
+    ///
+    /// Caller is responsible for ensuring `(P2, W2) == (P, W)`
+    #[inline]
+    pub(crate) fn cast<const P2: usize, const W2: usize>(self) -> HyperLogLog<P2, W2> {
+        HyperLogLog {
+            histogram: self.histogram,
+            registers: self.registers,
+            #[cfg(feature = "theta")]
+            kmv: self.kmv,
+            #[cfg(feature = "minhash")]
+            minhash: self.minhash,
+        }
+    }
 
     /// Create new instance of `HyperLogLog` representation from items
+    ///
+    /// `items` must be distinct encoded hashes, like the contents of a sparse
+    /// representation: their number is kept as the promotion count, and is the
+    /// exact estimate until the next insert or merge.
     #[inline]
     pub(crate) fn new(items: &[u32]) -> Self {
+        let mut histogram = vec![0; 1 << W];
+        histogram[0] = Self::M as u32;
         let mut hll = Self {
-            zeros: Self::M as u32,
-            harmonic_sum: Self::M as f32,
+            histogram: histogram.into_boxed_slice(),
             registers: vec![0; Self::HLL_SLICE_LEN],
+            #[cfg(feature = "theta")]
+            kmv: Some(Kmv::default()),
+            #[cfg(feature = "minhash")]
+            minhash: Some(MinHash::default()),
         };
 
         for &h in items.iter() {
@@ -37,10 +116,118 @@ impl<const P: usize, const W: usize> HyperLogLog<P, W> {
                 panic!("inserting into hll rep must yield none");
             };
         }
+        hll.set_promoted_count(Some(items.len() as u32));
+        hll.registers[Self::COUNT_IDX] |= Self::EXACT_COUNT;
 
         hll
     }
 
+    /// Create new instance of `HyperLogLog` representation promoted from the
+    /// items of a full exact representation, and the new item `h` that
+    /// overflowed it
+    #[inline]
+    pub(crate) fn promote(items: &[u32], h: u32) -> Self {
+        let mut hll = Self::new(items);
+        hll.insert_encoded_hash(h);
+        hll.set_promoted_count(Some(items.len() as u32 + 1));
+        hll
+    }
+
+    /// Number of distinct elements when promoted from an exact representation,
+    /// a lower bound of the cardinality ever since
+    ///
+    /// The estimate never goes below it, so that the HyperLogLog error doesn't
+    /// drop the estimate right after promotion. Inserts keep it, and merging
+    /// `HyperLogLog` representations keeps the smallest count: a larger one
+    /// may come from a promotion that another grouping of the same merges
+    /// never goes through, so this keeps merges associative. Registers from
+    /// outside a sketch have none.
+    ///
+    /// When promoted from exactly its items, rather than by the element that
+    /// overflowed them, the count is the estimate until the next insert or
+    /// merge (see `is_count_exact`), so that forcing a promotion doesn't
+    /// change the estimate.
+    ///
+    /// Stored as `count + 1` in a spare `registers` element, so that zeroed
+    /// registers mean none, with the `EXACT_COUNT` flag, and serialized with
+    /// them while it still changes the estimate.
+    #[inline]
+    fn promoted_count(&self) -> Option<u32> {
+        (self.registers[Self::COUNT_IDX] & !Self::EXACT_COUNT).checked_sub(1)
+    }
+
+    /// Whether the promotion count is still exactly the number of distinct
+    /// elements, see `promoted_count`
+    #[inline]
+    fn is_count_exact(&self) -> bool {
+        self.registers[Self::COUNT_IDX] & Self::EXACT_COUNT != 0
+    }
+
+    #[inline]
+    fn set_promoted_count(&mut self, count: Option<u32>) {
+        self.registers[Self::COUNT_IDX] = count.map_or(0, |count| count + 1);
+    }
+
+    /// Keep the smaller of this and `rhs`'s promotion counts, see `promoted_count`
+    #[inline]
+    fn merge_promoted_count(&mut self, rhs: Option<u32>) {
+        let count = self
+            .promoted_count()
+            .zip(rhs)
+            .map(|(lhs, rhs)| lhs.min(rhs));
+        self.set_promoted_count(count);
+    }
+
+    /// Promotion count that the estimate depends on: none once it's not exact
+    /// and the registers alone estimate at least as much
+    fn effective_promoted_count(&self) -> Option<u32> {
+        self.promoted_count().filter(|&count| {
+            self.is_count_exact() || f64::from(count) > self.estimate_registers_f64()
+        })
+    }
+
+    /// Spare `registers` element holding the promotion count, as serialized:
+    /// zero once it no longer changes the estimate
+    #[cfg(feature = "with_serde")]
+    #[inline]
+    pub(crate) fn count_word(&self) -> u32 {
+        match self.effective_promoted_count() {
+            Some(_) => self.registers[Self::COUNT_IDX],
+            None => 0,
+        }
+    }
+
+    /// Restore a promotion count serialized by `count_word`, or return `false`
+    /// if it's larger than any promotion gives
+    #[cfg(feature = "with_serde")]
+    pub(crate) fn set_count_word(&mut self, word: u32) -> bool {
+        if !Self::is_valid_count_word(word) {
+            return false;
+        }
+        self.registers[Self::COUNT_IDX] = word;
+        true
+    }
+
+    /// Whether a promotion count serialized by `count_word` is at most the
+    /// largest that any promotion gives
+    #[cfg(feature = "with_serde")]
+    pub(crate) fn is_valid_count_word(word: u32) -> bool {
+        word & !Self::EXACT_COUNT <= Self::MAX_PROMOTED_COUNT + 1
+    }
+
+    /// Promotion count serialized by `count_word`, see `promoted_count`
+    #[cfg(feature = "merge_from_serialized")]
+    pub(crate) fn count_from_word(word: u32) -> Option<u32> {
+        (word & !Self::EXACT_COUNT).checked_sub(1)
+    }
+
+    /// Registers without the spare elements: the one that stays zero, and the
+    /// promotion count
+    #[inline]
+    pub(crate) fn register_words(&self) -> &[u32] {
+        &self.registers[..Self::SPARE_IDX]
+    }
+
     /// Return normal index and rank from encoded sparse hash
     #[inline]
     fn decode_hash(h: u32) -> (u32, u32) {
@@ -49,18 +236,27 @@ impl<const P: usize, const W: usize> HyperLogLog<P, W> {
         (idx, rank)
     }
 
-    /// Insert encoded hash into HyperLogLog representation
+    /// Insert encoded hash into HyperLogLog representation, returning whether
+    /// the register increased
     #[inline]
-    fn update_rank(&mut self, idx: u32, new_rank: u32) {
+    fn update_rank(&mut self, idx: u32, new_rank: u32) -> bool {
         let old_rank = self.get_register(idx);
         if new_rank > old_rank {
             self.set_register(idx, old_rank, new_rank);
         }
+        new_rank > old_rank
+    }
+
+    /// Check whether inserting encoded hash `h` would leave the registers unchanged
+    #[inline]
+    pub(crate) fn covers_encoded_hash(&self, h: u32) -> bool {
+        let (idx, rank) = Self::decode_hash(h);
+        self.get_register(idx) >= rank
     }
 
     /// Get HyperLogLog `idx` register
     #[inline]
-    fn get_register(&self, idx: u32) -> u32 {
+    pub(crate) fn get_register(&self, idx: u32) -> u32 {
         let bit_idx = (idx as usize) * W;
         let u32_idx = bit_idx / 32;
         let bit_pos = bit_idx % 32;
@@ -76,6 +272,119 @@ impl<const P: usize, const W: usize> HyperLogLog<P, W> {
         ((bits[0] >> bit_pos) & mask_1) | ((bits[1] & mask_2) << bits_1)
     }
 
+    /// Decode all HyperLogLog register ranks in index order
+    pub(crate) fn decoded_registers(&self) -> Vec<u8> {
+        self.iter_registers().collect()
+    }
+
+    /// Iterate HyperLogLog register ranks in index order, decoding lazily
+    pub(crate) fn iter_registers(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..Self::M as u32).map(|idx| self.get_register(idx) as u8)
+    }
+
+    /// Count registers by rank, scanning them: index `r` holds the number of
+    /// registers with rank `r`
+    pub(crate) fn count_ranks(&self) -> Vec<u32> {
+        let mut histogram = vec![0; 1 << W];
+        for idx in 0..Self::M as u32 {
+            histogram[self.get_register(idx) as usize] += 1;
+        }
+        histogram
+    }
+
+    /// Maximum-likelihood cardinality estimate
+    ///
+    /// Under the Poisson model with rate `x = n / M`, a register holds rank 0
+    /// with probability `exp(-x)`, rank `1 <= k <= q` with probability
+    /// `exp(-x/2^k) * (1 - exp(-x/2^k))`, and the maximum rank `q + 1` with
+    /// probability `1 - exp(-x/2^q)`. Setting the derivative of the
+    /// log-likelihood to zero gives
+    ///
+    /// `c_0 + sum_{k=1}^{q} c_k/2^k = sum_{k=1}^{q} c_k/2^k/(exp(x/2^k) - 1) + c_{q+1}/2^q/(exp(x/2^q) - 1)`
+    ///
+    /// where `c_k` is the number of registers with rank `k`. The right side is
+    /// strictly decreasing in `x`, so the unique root is found by secant
+    /// iteration, bracketed to guarantee convergence (the Illinois variant of
+    /// regula falsi).
+    ///
+    /// Returns `usize::MAX` if every register holds the maximum rank.
+    ///
+    /// [Ertl, 2017](https://arxiv.org/abs/1702.01284)
+    pub(crate) fn estimate_mle(&self) -> usize {
+        (Self::estimate_mle_from_histogram(&self.histogram) + 0.5) as usize
+    }
+
+    /// Unrounded maximum-likelihood estimate from a register histogram, see
+    /// `estimate_mle`
+    ///
+    /// Returns infinity if every register holds the maximum rank.
+    pub(crate) fn estimate_mle_from_histogram(histogram: &[u32]) -> f64 {
+        let max_rank = Self::MAX_RANK;
+        let q = max_rank - 1;
+        // counts by rank, with all saturated registers counted at `max_rank`
+        let mut c = [0; 64];
+        c[..max_rank].copy_from_slice(&histogram[..max_rank]);
+        c[max_rank] = histogram[max_rank..].iter().sum();
+
+        if c[0] as usize == Self::M {
+            return 0.0;
+        }
+        if c[max_rank] as usize == Self::M {
+            return f64::INFINITY;
+        }
+
+        let a = f64::from(c[0])
+            + (1..=q)
+                .map(|k| f64::from(c[k]) / (1u64 << k) as f64)
+                .sum::<f64>();
+        let g = |x: f64| {
+            let ranked: f64 = (1..=q)
+                .filter(|&k| c[k] > 0)
+                .map(|k| {
+                    let scale = (1u64 << k) as f64;
+                    f64::from(c[k]) / scale / exp_m1(x / scale)
+                })
+                .sum();
+            let scale = (1u64 << q) as f64;
+            ranked + f64::from(c[max_rank]) / scale / exp_m1(x / scale) - a
+        };
+
+        // g is positive near zero and negative for large x
+        let (mut lo, mut hi) = (1.0 / Self::M as f64, 1.0);
+        while g(lo) <= 0.0 {
+            lo /= 2.0;
+        }
+        while g(hi) > 0.0 {
+            hi *= 2.0;
+        }
+        let (mut g_lo, mut g_hi) = (g(lo), g(hi));
+        let mut x = hi;
+        let mut kept_lo = None;
+        for _ in 0..100 {
+            x = (lo * g_hi - hi * g_lo) / (g_hi - g_lo);
+            let g_x = g(x);
+            if g_x == 0.0 || hi - lo <= x * 1e-12 {
+                break;
+            }
+            // halve the weight of an endpoint kept twice in a row, to avoid
+            // regula falsi stagnating on one side of the root
+            if g_x > 0.0 {
+                (lo, g_lo) = (x, g_x);
+                if kept_lo == Some(false) {
+                    g_hi /= 2.0;
+                }
+                kept_lo = Some(false);
+            } else {
+                (hi, g_hi) = (x, g_x);
+                if kept_lo == Some(true) {
+                    g_lo /= 2.0;
+                }
+                kept_lo = Some(true);
+            }
+        }
+        x * Self::M as f64
+    }
+
     /// Set HyperLogLog `idx` register to new value `rank`
     #[inline]
     fn set_register(&mut self, idx: u32, old_rank: u32, new_rank: u32) {
@@ -97,16 +406,35 @@ impl<const P: usize, const W: usize> HyperLogLog<P, W> {
         bits[1] &= !mask_2;
         bits[1] |= (new_rank >> bits_1) & mask_2;
 
-        // Update HyperLogLog's number of zero registers and harmonic sum
+        self.update_stats(old_rank, new_rank);
+    }
 
-        self.zeros -= u32::from(old_rank == 0) & u32::from(self.zeros > 0);
-        self.harmonic_sum -= 1.0 / ((1u64 << u64::from(old_rank)) as f32);
-        self.harmonic_sum += 1.0 / ((1u64 << u64::from(new_rank)) as f32);
+    /// Move a register from `old_rank` to `new_rank` in the histogram
+    #[inline]
+    fn update_stats(&mut self, old_rank: u32, new_rank: u32) {
+        self.histogram[old_rank as usize] -= 1;
+        self.histogram[new_rank as usize] += 1;
     }
 
     /// Merge two `HyperLogLog` representations.
     #[inline]
     pub(crate) fn merge(&mut self, rhs: &HyperLogLog<P, W>) {
+        self.merge_promoted_count(rhs.promoted_count());
+        #[cfg(feature = "theta")]
+        self.merge_kmv(rhs.kmv.as_ref());
+        #[cfg(feature = "minhash")]
+        self.merge_minhash(rhs.minhash.as_ref());
+        #[cfg(feature = "simd")]
+        if Self::SIMD_MERGE {
+            self.merge_simd(rhs);
+            return;
+        }
+        self.merge_scalar(rhs);
+    }
+
+    /// Merge two `HyperLogLog` representations one register at a time.
+    #[inline]
+    fn merge_scalar(&mut self, rhs: &HyperLogLog<P, W>) {
         for idx in 0..Self::M as u32 {
             let lhs_rank = self.get_register(idx);
             let rhs_rank = rhs.get_register(idx);
@@ -116,9 +444,203 @@ impl<const P: usize, const W: usize> HyperLogLog<P, W> {
         }
     }
 
-    /// Merge two `HyperLogLog` representations.
+    /// Registers tile `u32` words evenly, so the vectorized merge can take the
+    /// max of all register fields in a word without crossing word boundaries.
+    #[cfg(feature = "simd")]
+    const SIMD_MERGE: bool = 32 % W == 0;
+
+    /// Merge two `HyperLogLog` representations, taking the max of
+    /// `SIMD_LANES` words of registers per iteration.
+    ///
+    /// Must only be used when `SIMD_MERGE` holds. The histogram is updated for
+    /// every increased register, exactly like `merge_scalar`, so both produce
+    /// identical results.
+    #[cfg(feature = "simd")]
+    fn merge_simd(&mut self, rhs: &HyperLogLog<P, W>) {
+        use core::simd::cmp::{SimdOrd, SimdPartialEq};
+        use core::simd::Simd;
+
+        const SIMD_LANES: usize = 8;
+        debug_assert!(Self::SIMD_MERGE);
+
+        let words = Self::M * W / 32;
+        let mask = Simd::<u32, SIMD_LANES>::splat((1 << W) - 1);
+
+        for chunk_start in (0..words - words % SIMD_LANES).step_by(SIMD_LANES) {
+            let chunk = chunk_start..chunk_start + SIMD_LANES;
+            let lhs_words = Simd::<u32, SIMD_LANES>::from_slice(&self.registers[chunk.clone()]);
+            let rhs_words = Simd::<u32, SIMD_LANES>::from_slice(&rhs.registers[chunk.clone()]);
+            let mut merged = Simd::splat(0);
+            for shift in (0..32).step_by(W) {
+                let shift = Simd::splat(shift as u32);
+                let lhs_ranks = (lhs_words >> shift) & mask;
+                let rhs_ranks = (rhs_words >> shift) & mask;
+                merged |= lhs_ranks.simd_max(rhs_ranks) << shift;
+            }
+            if merged.simd_eq(lhs_words).all() {
+                continue;
+            }
+            merged.copy_to_slice(&mut self.registers[chunk]);
+
+            for (old, new) in lhs_words.to_array().into_iter().zip(merged.to_array()) {
+                for shift in (0..32).step_by(W) {
+                    let old_rank = (old >> shift) & ((1 << W) - 1);
+                    let new_rank = (new >> shift) & ((1 << W) - 1);
+                    if new_rank > old_rank {
+                        self.update_stats(old_rank, new_rank);
+                    }
+                }
+            }
+        }
+
+        // Registers in words not covered by full chunks
+        let tail_start = words - words % SIMD_LANES;
+        for idx in (tail_start * 32 / W) as u32..Self::M as u32 {
+            let lhs_rank = self.get_register(idx);
+            let rhs_rank = rhs.get_register(idx);
+            if rhs_rank > lhs_rank {
+                self.set_register(idx, lhs_rank, rhs_rank);
+            }
+        }
+    }
+
+    /// Merge the smallest encoded hashes of merged registers, which are unknown
+    /// for registers from outside a sketch
+    #[cfg(feature = "theta")]
+    fn merge_kmv(&mut self, rhs: Option<&Kmv>) {
+        match (&mut self.kmv, rhs) {
+            (Some(lhs), Some(rhs)) => lhs.merge(rhs),
+            (lhs, _) => *lhs = None,
+        }
+    }
+
+    /// Merge the MinHash bins of merged registers, which are unknown for
+    /// registers from outside a sketch
+    #[cfg(feature = "minhash")]
+    fn merge_minhash(&mut self, rhs: Option<&MinHash>) {
+        match (&mut self.minhash, rhs) {
+            (Some(lhs), Some(rhs)) => lhs.merge(rhs),
+            (lhs, _) => *lhs = None,
+        }
+    }
+
+    /// Merge a `HyperLogLog` representation with narrower or equal registers.
+    pub(crate) fn merge_narrower<const W2: usize>(&mut self, rhs: &HyperLogLog<P, W2>) {
+        self.merge_ranks((0..Self::M as u32).map(|idx| rhs.get_register(idx)), None);
+    }
+
+    /// Merge register ranks given in index order, and their promotion count
+    /// if known
+    pub(crate) fn merge_ranks(&mut self, ranks: impl IntoIterator<Item = u32>, count: Option<u32>) {
+        self.merge_promoted_count(count);
+        #[cfg(feature = "theta")]
+        self.merge_kmv(None);
+        #[cfg(feature = "minhash")]
+        self.merge_minhash(None);
+        for (idx, rank) in (0..Self::M as u32).zip(ranks) {
+            self.update_rank(idx, rank);
+        }
+    }
+
+    /// Merge many `HyperLogLog` representations, visiting each register once.
+    pub(crate) fn merge_many(&mut self, rhs: &[&HyperLogLog<P, W>]) {
+        for hll in rhs {
+            self.merge_promoted_count(hll.promoted_count());
+        }
+        #[cfg(feature = "theta")]
+        for hll in rhs {
+            self.merge_kmv(hll.kmv.as_ref());
+        }
+        #[cfg(feature = "minhash")]
+        for hll in rhs {
+            self.merge_minhash(hll.minhash.as_ref());
+        }
+        for idx in 0..Self::M as u32 {
+            let lhs_rank = self.get_register(idx);
+            let rhs_rank = rhs.iter().map(|hll| hll.get_register(idx)).max();
+            if let Some(rhs_rank) = rhs_rank.filter(|&rank| rank > lhs_rank) {
+                self.set_register(idx, lhs_rank, rhs_rank);
+            }
+        }
+    }
+
+    /// Recount the histogram from the registers
+    ///
+    /// Register updates keep the histogram exact, so this only repairs one
+    /// that was modified by other means.
+    pub(crate) fn refresh_stats(&mut self) {
+        self.histogram = self.count_ranks().into_boxed_slice();
+    }
+
+    /// Number of zero registers
+    #[inline]
+    pub(crate) fn zeros(&self) -> u32 {
+        self.histogram[0]
+    }
+
+    /// Harmonic sum of the registers, see `stats_from_histogram`
+    #[cfg(any(test, feature = "with_serde"))]
+    #[inline]
+    pub(crate) fn harmonic_sum(&self) -> f64 {
+        Self::stats_from_histogram(&self.histogram).1
+    }
+
+    /// Number of zero registers and harmonic sum of registers with the given
+    /// histogram
+    ///
+    /// The sum is accumulated exactly in fixed point (see `SUM_SHIFT`) and
+    /// rounded once, so it only depends on the registers, not on the order
+    /// they were updated or merged in.
+    #[inline]
+    pub(crate) fn stats_from_histogram(histogram: &[u32]) -> (u32, f64) {
+        let sum: u128 = (histogram.iter().enumerate())
+            .map(|(rank, &count)| u128::from(count) << (Self::SUM_SHIFT - rank))
+            .sum();
+        let harmonic_sum = sum as f64 / (1u128 << Self::SUM_SHIFT) as f64;
+        (histogram[0], harmonic_sum)
+    }
+
+    /// Unrounded estimate from the registers alone, ignoring the promotion count
+    #[inline]
+    fn estimate_registers_f64(&self) -> f64 {
+        Self::estimate_f64_from_histogram(&self.histogram)
+    }
+
+    /// Estimate from the register histogram (see `estimate_sketch`), in time
+    /// proportional to its `2^W` ranks rather than to the registers
+    #[inline]
+    pub(crate) fn estimate_from_histogram(histogram: &[u32]) -> usize {
+        (Self::estimate_f64_from_histogram(histogram) + 0.5) as usize
+    }
+
+    /// Unrounded estimate from the register histogram, see
+    /// `estimate_from_histogram`
+    #[inline]
+    pub(crate) fn estimate_f64_from_histogram(histogram: &[u32]) -> f64 {
+        let (zeros, harmonic_sum) = Self::stats_from_histogram(histogram);
+        let m = Self::M as f64;
+        let estimate = alpha(Self::M) * ((Self::M * (Self::M - zeros as usize)) as f64)
+            / (harmonic_sum + beta_horner(f64::from(zeros), P));
+        // small-range correction: linear counting is more accurate while many
+        // registers are zero, and is blended into LogLog-Beta between half the
+        // threshold and the threshold
+        let estimate = if zeros == 0 {
+            estimate
+        } else {
+            let half = LINEAR_COUNTING_THRESHOLD[P - 4] / 2.0;
+            let linear = m * ln(m / f64::from(zeros));
+            let weight = ((linear - half) / half).clamp(0.0, 1.0);
+            linear * (1.0 - weight) + estimate * weight
+        };
+        if estimate > Self::SATURATION_THRESHOLD {
+            return Self::estimate_mle_from_histogram(histogram);
+        }
+        estimate
+    }
+
+    /// Create new instance of `HyperLogLog` from registers, copying their ranks
+    /// and counting them into the histogram
     #[inline]
-    #[cfg(feature = "with_serde")]
     pub(crate) fn from_registers(registers: Vec<u32>) -> Self {
         // caller is responsible for checking that registers.len() == Self::HLL_SLICE_LEN
         let mut lhs = Self::new(&[]);
@@ -131,8 +653,64 @@ impl<const P: usize, const W: usize> HyperLogLog<P, W> {
                 lhs.set_register(idx, lhs_rank, rhs_rank);
             }
         }
+        lhs.set_promoted_count(None);
+        #[cfg(feature = "theta")]
+        {
+            lhs.kmv = None;
+        }
+        #[cfg(feature = "minhash")]
+        {
+            lhs.minhash = None;
+        }
         lhs
     }
+
+    /// Create new instance of `HyperLogLog` from registers and their
+    /// histogram as they are, without scanning the registers
+    ///
+    /// Caller is responsible for checking that registers.len() == Self::HLL_SLICE_LEN
+    /// and histogram.len() == 1 << W, and that the histogram matches the
+    /// registers. Unlike `from_registers`, the spare elements of `registers`
+    /// are kept unchecked, including the promotion count.
+    #[cfg(feature = "with_serde")]
+    pub(crate) fn from_registers_unchecked(registers: Vec<u32>, histogram: Box<[u32]>) -> Self {
+        Self {
+            histogram,
+            registers,
+            #[cfg(feature = "theta")]
+            kmv: None,
+            #[cfg(feature = "minhash")]
+            minhash: None,
+        }
+    }
+
+    /// Length in bytes of the registers packed without padding
+    #[cfg(any(feature = "with_serde", feature = "redis_compat"))]
+    pub(crate) const PACKED_LEN: usize = Self::M * W / 8;
+
+    /// Registers packed without padding, as little-endian bytes
+    #[cfg(any(feature = "with_serde", feature = "redis_compat"))]
+    pub(crate) fn to_packed_bytes(&self) -> Vec<u8> {
+        self.registers
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .take(Self::PACKED_LEN)
+            .collect()
+    }
+
+    /// Create new instance of `HyperLogLog` from registers packed by `to_packed_bytes`
+    ///
+    /// Caller is responsible for checking that `bytes.len() == Self::PACKED_LEN`
+    #[cfg(any(feature = "with_serde", feature = "redis_compat"))]
+    pub(crate) fn from_packed_bytes(bytes: &[u8]) -> Self {
+        let mut registers = vec![0; Self::HLL_SLICE_LEN];
+        for (word, chunk) in registers.iter_mut().zip(bytes.chunks(4)) {
+            let mut le_bytes = [0; 4];
+            le_bytes[..chunk.len()].copy_from_slice(chunk);
+            *word = u32::from_le_bytes(le_bytes);
+        }
+        Self::from_registers(registers)
+    }
 }
 
 impl<const P: usize, const W: usize> SketchTrait<P, W> for HyperLogLog<P, W> {
@@ -141,17 +719,59 @@ impl<const P: usize, const W: usize> SketchTrait<P, W> for HyperLogLog<P, W> {
     fn insert_encoded_hash(&mut self, h: u32) -> Option<Sketch<P, W>> {
         let (idx, rank) = Self::decode_hash(h);
         self.update_rank(idx, rank);
+        self.registers[Self::COUNT_IDX] &= !Self::EXACT_COUNT;
+        #[cfg(feature = "theta")]
+        if let Some(kmv) = &mut self.kmv {
+            kmv.insert(h);
+        }
+        #[cfg(feature = "minhash")]
+        if let Some(minhash) = &mut self.minhash {
+            minhash.insert(h);
+        }
         None
     }
 
     /// Return cardinality estimate of `HyperLogLog` representation
+    ///
+    /// While many registers are still zero, linear counting is blended in, as
+    /// in the HyperLogLog++ small-range correction (see
+    /// `LINEAR_COUNTING_THRESHOLD`).
+    ///
+    /// LogLog-Beta does not account for registers capped at `MAX_RANK`, so it
+    /// underestimates (and can even decrease under further inserts) once many
+    /// registers saturate, which happens early for small `W`. Above
+    /// `SATURATION_THRESHOLD`, the maximum-likelihood estimate is used
+    /// instead: it models saturated registers explicitly, from the register
+    /// histogram kept up to date by inserts and merges, so it doesn't scan
+    /// the registers either.
+    ///
+    /// The estimate is never below the promotion count, and is the count
+    /// itself while it's exact (see `promoted_count`).
     #[inline]
     fn estimate_sketch(&self) -> usize {
-        let zeros = self.zeros;
-        let sum = f64::from(self.harmonic_sum);
-        let estimate = alpha(Self::M) * ((Self::M * (Self::M - zeros as usize)) as f64)
-            / (sum + beta_horner(f64::from(zeros), P));
-        (estimate + 0.5) as usize
+        match self.promoted_count() {
+            Some(count) if self.is_count_exact() => count as usize,
+            count => {
+                let estimate = Self::estimate_from_histogram(&self.histogram);
+                count.map_or(estimate, |count| estimate.max(count as usize))
+            }
+        }
+    }
+
+    /// Return the unrounded cardinality estimate, see `estimate_sketch`
+    #[inline]
+    fn estimate_sketch_f64(&self) -> f64 {
+        match self.promoted_count() {
+            Some(count) if self.is_count_exact() => f64::from(count),
+            Some(count) => self.estimate_registers_f64().max(f64::from(count)),
+            None => self.estimate_registers_f64(),
+        }
+    }
+
+    /// Check if all `HyperLogLog` registers are zero
+    #[inline]
+    fn is_empty_sketch(&self) -> bool {
+        self.zeros() as usize == Self::M
     }
 
     /// Return memory size of `HyperLogLog`
@@ -159,6 +779,18 @@ impl<const P: usize, const W: usize> SketchTrait<P, W> for HyperLogLog<P, W> {
     fn size_of(&self) -> usize {
         size_of_val(self)
     }
+
+    /// Return memory size of `HyperLogLog`, including its heap-allocated
+    /// registers and histogram
+    #[inline]
+    fn heap_size(&self) -> usize {
+        let heap = (self.registers.capacity() + self.histogram.len()) * size_of::<u32>();
+        #[cfg(feature = "theta")]
+        let heap = heap + self.kmv.as_ref().map_or(0, Kmv::heap_size);
+        #[cfg(feature = "minhash")]
+        let heap = heap + self.minhash.as_ref().map_or(0, MinHash::heap_size);
+        size_of_val(self) + heap
+    }
 }
 
 impl<const P: usize, const W: usize> From<Vec<u32>> for HyperLogLog<P, W> {
@@ -170,17 +802,60 @@ impl<const P: usize, const W: usize> From<Vec<u32>> for HyperLogLog<P, W> {
 }
 
 impl<const P: usize, const W: usize> PartialEq for HyperLogLog<P, W> {
+    /// Compare the registers, and the promotion counts where they change the
+    /// estimate
     fn eq(&self, other: &Self) -> bool {
-        self.registers == other.registers
+        self.register_words() == other.register_words()
+            && (self.promoted_count() == other.promoted_count()
+                || self.effective_promoted_count() == other.effective_promoted_count())
+    }
+}
+
+impl<const P: usize, const W: usize> Eq for HyperLogLog<P, W> {}
+
+impl<const P: usize, const W: usize> Hash for HyperLogLog<P, W> {
+    /// Hash the registers and the promotion count where it changes the
+    /// estimate, consistent with `PartialEq`: the histogram is derived from
+    /// the registers.
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.register_words().hash(state);
+        self.effective_promoted_count().hash(state);
     }
 }
 
 impl<const P: usize, const W: usize> Debug for HyperLogLog<P, W> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         f.write_str(&self.to_string())
     }
 }
 
+/// Natural logarithm
+#[inline]
+fn ln(x: f64) -> f64 {
+    #[cfg(feature = "std")]
+    return x.ln();
+    #[cfg(not(feature = "std"))]
+    return libm::log(x);
+}
+
+/// Square root
+#[inline]
+fn sqrt(x: f64) -> f64 {
+    #[cfg(feature = "std")]
+    return x.sqrt();
+    #[cfg(not(feature = "std"))]
+    return libm::sqrt(x);
+}
+
+/// `exp(x) - 1`, accurate for small `x`
+#[inline]
+fn exp_m1(x: f64) -> f64 {
+    #[cfg(feature = "std")]
+    return x.exp_m1();
+    #[cfg(not(feature = "std"))]
+    return libm::expm1(x);
+}
+
 /// Parameter for bias correction
 #[inline]
 fn alpha(m: usize) -> f64 {
@@ -198,7 +873,7 @@ fn alpha(m: usize) -> f64 {
 #[inline]
 fn beta_horner(z: f64, precision: usize) -> f64 {
     let beta = BETA[precision - 4];
-    let zl = (z + 1.0).ln();
+    let zl = ln(z + 1.0);
     let mut res = 0.0;
     for i in (1..8).rev() {
         res = res * zl + beta[i];
@@ -206,6 +881,15 @@ fn beta_horner(z: f64, precision: usize) -> f64 {
     res * zl + beta[0] * z
 }
 
+/// Linear counting estimates below which HyperLogLog++ prefers them, for
+/// precision in [4..18] range.
+///
+/// Paper: https://research.google/pubs/pub40671/
+const LINEAR_COUNTING_THRESHOLD: [f64; 15] = [
+    10.0, 20.0, 40.0, 80.0, 220.0, 400.0, 900.0, 1800.0, 3100.0, 6500.0, 11500.0, 20000.0, 50000.0,
+    120000.0, 350000.0,
+];
+
 /// LogLog-Beta polynomial coefficients for precision in [4..18] range.
 const BETA: [[f64; 8]; 15] = [
     // p = 4
@@ -293,7 +977,7 @@ const BETA: [[f64; 8]; 15] = [
         -1.65687801845180e-02,
         -7.95829341087617e-02,
         4.71830602102918e-02,
-        -7.81372902346934e03,
+        -7.81372902346934e-03,
         5.84268708489995e-04,
     ],
     // p = 12
@@ -378,9 +1062,127 @@ const BETA: [[f64; 8]; 15] = [
 #[cfg(test)]
 mod tests {
     use super::*;
+    #[cfg(feature = "simd")]
+    use test_case::test_case;
 
     #[test]
     fn hyerloglog_size() {
-        assert_eq!(std::mem::size_of::<HyperLogLog<0, 0>>(), 32);
+        let theta = if cfg!(feature = "theta") { 24 } else { 0 };
+        let minhash = if cfg!(feature = "minhash") { 8 } else { 0 };
+        assert_eq!(
+            core::mem::size_of::<HyperLogLog<0, 0>>(),
+            40 + theta + minhash
+        );
+    }
+
+    #[test]
+    fn test_harmonic_sum_exact() {
+        type Hll = HyperLogLog<18, 6>;
+        let mut hll = Hll::new(&[]);
+        // replay each register update into an `f64` accumulator
+        let mut replayed = Hll::M as f64;
+        for i in 0..1_000_000u64 {
+            let h = crate::Element::<18, 6>::from_hasher_default::<wyhash::WyHash>(i).0;
+            let (idx, rank) = Hll::decode_hash(h);
+            let old_rank = hll.get_register(idx);
+            hll.insert_encoded_hash(h);
+            if rank > old_rank {
+                replayed -= 1.0 / (1u64 << old_rank) as f64;
+                replayed += 1.0 / (1u64 << rank) as f64;
+            }
+        }
+        assert_eq!(hll.histogram[..], hll.count_ranks()[..]);
+        let exact: f64 = (hll.count_ranks().iter().enumerate())
+            .map(|(rank, &count)| f64::from(count) / (1u64 << rank) as f64)
+            .sum();
+        let error = |sum: f64| (sum - exact).abs() / exact;
+        assert!(error(hll.harmonic_sum()) < 1e-15);
+        assert!(
+            error(replayed) < 1e-12,
+            "replayed error {}",
+            error(replayed)
+        );
+    }
+
+    #[test]
+    fn test_saturated_estimate_from_histogram() {
+        type Hll = HyperLogLog<4, 4>;
+        let mut hll = Hll::new(&[]);
+        for i in 0..100_000u64 {
+            let h = crate::Element::<4, 4>::from_hasher_default::<wyhash::WyHash>(i).0;
+            hll.insert_encoded_hash(h);
+        }
+        let mle = Hll::estimate_mle_from_histogram(&hll.count_ranks());
+        assert!(mle > Hll::SATURATION_THRESHOLD);
+        assert_eq!(hll.estimate_sketch_f64(), mle);
+    }
+
+    #[test]
+    fn test_merge_order_independent() {
+        type Hll = HyperLogLog<12, 6>;
+        let hll = |range: core::ops::Range<u64>| {
+            let mut hll = Hll::new(&[]);
+            for i in range {
+                let h = crate::Element::<12, 6>::from_hasher_default::<wyhash::WyHash>(i).0;
+                hll.insert_encoded_hash(h);
+            }
+            hll
+        };
+        let (a, b, c) = (hll(0..5_000), hll(3_000..40_000), hll(100..200));
+
+        let mut abc = a.clone();
+        abc.merge(&b);
+        abc.merge(&c);
+        let mut cba = c.clone();
+        cba.merge_many(&[&b, &a]);
+        let inserted = hll(0..40_000);
+
+        for merged in [&abc, &cba] {
+            assert_eq!(merged.registers, inserted.registers);
+            assert_eq!(merged.histogram, inserted.histogram);
+            assert_eq!(
+                merged.harmonic_sum().to_bits(),
+                inserted.harmonic_sum().to_bits()
+            );
+        }
+    }
+
+    #[cfg(feature = "simd")]
+    fn hll_from_range<const P: usize, const W: usize>(
+        range: core::ops::Range<u64>,
+    ) -> HyperLogLog<P, W> {
+        let mut hll = HyperLogLog::<P, W>::new(&[]);
+        for i in range {
+            let h = crate::Element::<P, W>::from_hashed(i.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+            hll.insert_encoded_hash(h.0);
+        }
+        hll
+    }
+
+    #[cfg(feature = "simd")]
+    fn check_simd_merge<const P: usize, const W: usize>(lhs_n: u64, rhs_n: u64) {
+        let lhs = hll_from_range::<P, W>(0..lhs_n);
+        let rhs = hll_from_range::<P, W>(lhs_n / 2..lhs_n / 2 + rhs_n);
+
+        let mut scalar = lhs.clone();
+        scalar.merge_scalar(&rhs);
+        let mut simd = lhs.clone();
+        simd.merge_simd(&rhs);
+
+        assert_eq!(simd.registers, scalar.registers);
+        assert_eq!(simd.histogram, scalar.histogram);
+    }
+
+    #[cfg(feature = "simd")]
+    #[test_case(0, 0; "empty")]
+    #[test_case(1_000, 0; "empty rhs")]
+    #[test_case(0, 1_000; "empty lhs")]
+    #[test_case(1_000, 1_000; "sparse")]
+    #[test_case(100_000, 50_000; "dense")]
+    #[test_case(10_000_000, 10_000_000; "saturated")]
+    fn test_simd_merge_matches_scalar(lhs_n: u64, rhs_n: u64) {
+        check_simd_merge::<4, 4>(lhs_n, rhs_n);
+        check_simd_merge::<8, 4>(lhs_n, rhs_n);
+        check_simd_merge::<14, 4>(lhs_n, rhs_n);
     }
 }
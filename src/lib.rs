@@ -19,7 +19,8 @@
 //! - Cardinality in [9..16] range - 72 bytes (array representation)
 //! - ...
 //! - Cardinality in [65..128] range - 520 bytes (array representation)
-//! - Cardinality in [129..] range - 3092 bytes (hyperloglog representation)
+//! - Cardinality in [129..771] range - 4 bytes per element, up to 3084 bytes (sparse representation)
+//! - Cardinality in [772..] range - 3092 bytes (hyperloglog representation)
 //!
 //! ## Low latency
 //! - Auto-vectorization for slice operations via compiler hints
@@ -28,9 +29,11 @@
 //!   stored and updated dynamically as more data being inserted,
 //!   allowing to have truly constant `estimate` operations.
 //! - Efficient polynomial computation using Horner's method.
+//! - With the nightly-only `simd` feature, HyperLogLog merges of
+//!   `W = 4` sketches take the max of many registers at once using portable SIMD.
 //!
 //! ## High accuracy
-//! - For small cardinality range (<= 128 for P = 12, W = 6)
+//! - For small cardinality range (<= 771 for P = 12, W = 6, and at most 1024)
 //!   cardinality counted very accurately (within hash collisions chance)
 //! - For large cardinality range HyperLogLog++ is used with LogLog-Beta bias correction.
 //!   - Expected error (1.04 / sqrt(2^P)):
@@ -40,21 +43,67 @@
 //!     - P = 18, W = 6: 0.0020
 //!
 //! # Data storage format
-//! Cardinality estimator stores data in one of the three representations:
+//! Cardinality estimator stores data in one of the four representations:
 //! - `Small` representation - see `small` module for more details.
 //! - `Array` representation - see `array` module for more details.
+//! - `Sparse` representation - see `sparse` module for more details.
 //! - `HyperLogLog` representation - see `hyperloglog` module for more details
 //!
 //! # Data Storage Format
-//! The cardinality estimator stores data in one of three formats: `Small`, `Array`, and `HyperLogLog`.
-//! See corresponding modules (`small`, `array`, `hyperloglog`) for more details.
+//! The cardinality estimator stores data in one of four formats: `Small`, `Array`, `Sparse`, and `HyperLogLog`.
+//! See corresponding modules (`small`, `array`, `sparse`, `hyperloglog`) for more details.
+//!
+//! # `no_std` support
+//! The `std` feature is enabled by default. Without it, the crate is `no_std`
+//! and only requires `alloc`, using `libm` for floating-point math.
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
+#![cfg_attr(feature = "simd", feature(portable_simd))]
+#![cfg_attr(not(feature = "ffi"), forbid(unsafe_code))]
+
+extern crate alloc;
+
+#[cfg(feature = "arbitrary")]
+mod arbitrary;
 mod array;
+mod dynamic;
 mod element;
+#[cfg(feature = "ffi")]
+pub mod ffi;
 mod hyperloglog;
+#[cfg(any(feature = "redis_compat", feature = "presto_compat"))]
+mod interop;
+#[cfg(feature = "minhash")]
+mod minhash;
 #[cfg(feature = "with_serde")]
 mod serde;
 pub mod sketch;
 mod small;
+mod sparse;
+#[cfg(feature = "test_hasher")]
+mod test_hasher;
+#[cfg(feature = "theta")]
+mod theta;
+#[cfg(feature = "wasm")]
+mod wasm;
 
+pub use dynamic::{validate_params, DynSketch, MergeError, ParamError, Precision};
+#[cfg(feature = "with_digest")]
+pub use element::DigestPrefixHasher;
 pub use element::Element;
-pub use sketch::Sketch;
+#[cfg(feature = "redis_compat")]
+pub use interop::ExportError;
+#[cfg(any(feature = "redis_compat", feature = "presto_compat"))]
+pub use interop::ImportError;
+#[cfg(feature = "minhash")]
+pub use minhash::{signature_similarity, MINHASH_K};
+#[cfg(feature = "versioned_serde")]
+pub use serde::VersionedSketch;
+#[cfg(feature = "with_serde")]
+pub use serde::{CompactSketch, EstimateOnly, FinalizedError, HllView, TrustedSketch};
+pub use sketch::{
+    CapacityError, Confidence, HashBytesError, LoadError, ParseError, RepresentationKind, Sketch,
+};
+#[cfg(feature = "test_hasher")]
+pub use test_hasher::DeterministicHasher;
+#[cfg(feature = "wasm")]
+pub use wasm::WasmSketch;
@@ -0,0 +1,122 @@
+//! ## MinHash signature
+//! With the `minhash` feature, sketches also provide a fixed-size MinHash
+//! signature of their elements, for locality-sensitive hashing: sketches of
+//! similar sets have signatures that agree in many positions, so bands of
+//! signature positions can bucket candidate pairs before comparing them.
+//!
+//! It uses one-permutation hashing: each encoded hash is scrambled by a
+//! bijective mixer, whose top bits pick one of `MINHASH_K` bins, and each bin
+//! keeps the smallest scrambled value it has seen. The HyperLogLog
+//! representation updates the bins on insert, while the other representations
+//! compute them from the encoded hashes they store.
+
+use alloc::boxed::Box;
+use core::mem::size_of;
+
+/// Number of positions in a MinHash signature (see `Sketch::minhash_signature`)
+pub const MINHASH_K: usize = 128;
+
+/// Value of the signature positions whose bin is still empty
+const EMPTY: u32 = u32::MAX;
+
+/// Smallest scrambled encoded hash of each bin
+#[derive(Debug, Clone)]
+pub(crate) struct MinHash(Box<[u32; MINHASH_K]>);
+
+impl Default for MinHash {
+    fn default() -> Self {
+        Self(Box::new([EMPTY; MINHASH_K]))
+    }
+}
+
+impl MinHash {
+    /// Signature of the given encoded hashes
+    pub(crate) fn from_hashes(hashes: impl IntoIterator<Item = u32>) -> Self {
+        let mut minhash = Self::default();
+        for h in hashes {
+            minhash.insert(h);
+        }
+        minhash
+    }
+
+    /// Add an encoded hash, if it's the smallest of its bin
+    #[inline]
+    pub(crate) fn insert(&mut self, h: u32) {
+        let value = mix(h);
+        let bin = &mut self.0[(value >> (32 - MINHASH_K.trailing_zeros())) as usize];
+        *bin = (*bin).min(value);
+    }
+
+    /// Keep the smallest value of each bin of both signatures
+    pub(crate) fn merge(&mut self, rhs: &Self) {
+        for (lhs, &rhs) in self.0.iter_mut().zip(rhs.0.iter()) {
+            *lhs = (*lhs).min(rhs);
+        }
+    }
+
+    /// The smallest value of each bin, or `u32::MAX` for empty bins
+    pub(crate) fn signature(&self) -> [u32; MINHASH_K] {
+        *self.0
+    }
+
+    /// Heap-allocated bytes
+    pub(crate) fn heap_size(&self) -> usize {
+        MINHASH_K * size_of::<u32>()
+    }
+}
+
+/// Estimate the Jaccard similarity `|A ∩ B| / |A ∪ B|` of two sets from
+/// their MinHash signatures
+///
+/// Returns the fraction of equal positions among those that aren't empty in
+/// both signatures, which is within about `1 / sqrt(MINHASH_K)` of the
+/// Jaccard similarity for sets of more than a few hundred elements. Two empty
+/// signatures are identical, with a similarity of 1.
+pub fn signature_similarity(lhs: &[u32; MINHASH_K], rhs: &[u32; MINHASH_K]) -> f64 {
+    let (mut equal, mut used) = (0, 0);
+    for (&l, &r) in lhs.iter().zip(rhs) {
+        if l != EMPTY || r != EMPTY {
+            used += 1;
+            equal += u32::from(l == r);
+        }
+    }
+    if used == 0 {
+        1.0
+    } else {
+        f64::from(equal) / f64::from(used)
+    }
+}
+
+/// MurmurHash3 32-bit finalizer, a bijection spreading encoded hashes over
+/// all bits
+#[inline]
+fn mix(mut h: u32) -> u32 {
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2_ae35);
+    h ^ (h >> 16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_minhash_merge() {
+        let a = MinHash::from_hashes(0..1000);
+        let b = MinHash::from_hashes(500..3000);
+        let mut merged = a.clone();
+        merged.merge(&b);
+        assert_eq!(
+            merged.signature(),
+            MinHash::from_hashes(0..3000).signature()
+        );
+        assert!(merged.signature().iter().all(|&v| v != EMPTY));
+
+        let empty = MinHash::default().signature();
+        assert_eq!(signature_similarity(&empty, &empty), 1.0);
+        assert_eq!(signature_similarity(&merged.signature(), &empty), 0.0);
+        assert_eq!(signature_similarity(&a.signature(), &a.signature()), 1.0);
+    }
+}
@@ -1,10 +1,21 @@
 //! # Serde module for CardinalityEstimator
 
 use crate::array::{Array, MAX_CAPACITY as ARRAY_MAX_CAPACITY};
+use crate::element::Element;
 use crate::hyperloglog::HyperLogLog;
+#[cfg(any(feature = "hex", feature = "tokio"))]
+use crate::sketch::ParseError;
+use crate::sketch::Sketch;
+use crate::small::Small;
+use crate::sparse::Sparse;
+use alloc::format;
+#[cfg(feature = "hex")]
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::marker::PhantomData;
 use serde::de::{self, SeqAccess, Visitor};
 use serde::{ser::SerializeSeq, Deserialize, Serialize};
-use std::fmt;
 
 impl<const P: usize, const W: usize> Serialize for Array<P, W> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
@@ -43,6 +54,42 @@ impl<'de, const P: usize, const W: usize> Deserialize<'de> for Array<P, W> {
     }
 }
 
+impl<const P: usize, const W: usize> Serialize for Sparse<P, W> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.to_sorted_vec().serialize(serializer)
+    }
+}
+
+impl<'de, const P: usize, const W: usize> Deserialize<'de> for Sparse<P, W> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let items: Vec<u32> = Deserialize::deserialize(deserializer)?;
+        let found = items.len();
+        let max_len = Sparse::<P, W>::MAX_LEN;
+        if found <= ARRAY_MAX_CAPACITY || found > max_len {
+            return Err(de::Error::invalid_length(
+                found,
+                &format!(
+                    "sparse representation with more than {ARRAY_MAX_CAPACITY} and at most {max_len} items"
+                )
+                .as_str(),
+            ));
+        }
+        if items.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return Err(de::Error::invalid_value(
+                de::Unexpected::Seq,
+                &"sparse representation items in strictly ascending order",
+            ));
+        }
+        Ok(Sparse::from_sorted(items))
+    }
+}
+
 /// Serialize the HyperLogLog representation
 ///
 /// Serializing the zeros and harmonic_sum values is a choice that I'm rolling with
@@ -55,26 +102,61 @@ impl<'de, const P: usize, const W: usize> Deserialize<'de> for Array<P, W> {
 /// the serialied data is sequence of u32s:
 /// - 0: hll zeros
 /// - 1: harmonic_sum (f32 transmuted to u32)
-/// - 2..: registers array
+/// - 2..: registers array, with the spare element zeroed, and the
+///   promotion count (see `HyperLogLog::promoted_count`) as `count + 1` in the
+///   last element, or 0 for none
+/// - last (only with the `checksum` feature): CRC-32 of the registers array
 impl<const P: usize, const W: usize> Serialize for HyperLogLog<P, W> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.serialize_with(serializer, &[])
+    }
+}
+
+impl<const P: usize, const W: usize> HyperLogLog<P, W> {
+    /// Serialize the `u32` sequence described on `Serialize`, followed by `trailer`
+    fn serialize_with<S>(&self, serializer: S, trailer: &[u32]) -> Result<S::Ok, S::Error>
     where
         S: serde::Serializer,
     {
         // shouldn't be necessary, but things have really gone wrong somewhere if not:
         assert_eq!(Self::HLL_SLICE_LEN, self.registers.len());
 
-        let mut seq = serializer.serialize_seq(Some(Self::HLL_SLICE_LEN + 2))?;
-        seq.serialize_element(&self.zeros)?;
-        seq.serialize_element(&self.harmonic_sum.to_bits())?;
+        let mut seq = serializer.serialize_seq(Some(Self::SERIALIZED_LEN + trailer.len()))?;
+        seq.serialize_element(&self.zeros())?;
+        seq.serialize_element(&(self.harmonic_sum() as f32).to_bits())?;
 
-        for r in &self.registers {
-            seq.serialize_element(r)?;
+        let registers = (self.register_words().iter().copied()).chain([0, self.count_word()]);
+        for r in registers.clone() {
+            seq.serialize_element(&r)?;
+        }
+        #[cfg(feature = "checksum")]
+        seq.serialize_element(&crc32(registers))?;
+        for word in trailer {
+            seq.serialize_element(word)?;
         }
         seq.end()
     }
 }
 
+/// Number of trailing checksum elements in the serialized HyperLogLog representation
+const CHECKSUM_LEN: usize = if cfg!(feature = "checksum") { 1 } else { 0 };
+
+/// CRC-32 (IEEE) of `u32` words in little-endian byte order
+#[cfg(feature = "checksum")]
+fn crc32(words: impl IntoIterator<Item = u32>) -> u32 {
+    let mut crc = !0u32;
+    for byte in words.into_iter().flat_map(u32::to_le_bytes) {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
 struct TupleU32Visitor(usize);
 
 impl<'de> Visitor<'de> for TupleU32Visitor {
@@ -99,13 +181,13 @@ impl<'de> Visitor<'de> for TupleU32Visitor {
             };
             registers.push(el);
         }
-        if let Some(remaining) = access.size_hint() {
-            if remaining > 0 {
-                return Err(de::Error::invalid_length(
-                    expected_len,
-                    &format!("hyperloglog representation with length {expected_len} (hint says {remaining} extra in sequence)").as_str(),
-                ));
-            }
+        // `size_hint` is not reliable for all deserializers, so look for an
+        // extra element directly
+        if access.next_element::<u32>()?.is_some() {
+            return Err(de::Error::invalid_length(
+                expected_len + 1,
+                &format!("hyperloglog representation with length {expected_len}").as_str(),
+            ));
         }
         Ok(registers)
     }
@@ -119,30 +201,26 @@ impl<'de> Visitor<'de> for TupleU32Visitor {
 /// - the harmonic sum must match within some error
 ///
 /// otherwise there was likely some issue with the data in storage and it will be rejected.
-/// for now, the *stored* harmonic_sum will ultimately be used, so that the deserialized
-/// instance has the exact state of the pre-serialiezd one. it seems intuitively like this
-/// might have slightly higher accumulated floating-point error though?
+/// with the `checksum` feature, the registers must also match the trailing CRC-32 exactly.
+/// the stored values are only checked: the deserialized instance keeps the exact
+/// histogram counted from the registers, just like the pre-serialized one.
 ///
-/// cheaper/less safe deserialization paths may be added in the future
+/// `TrustedSketch` offers a cheaper, less safe deserialization path.
 impl<'de, const P: usize, const W: usize> Deserialize<'de> for HyperLogLog<P, W> {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: serde::Deserializer<'de>,
     {
-        let stuff = deserializer.deserialize_seq(TupleU32Visitor(Self::HLL_SLICE_LEN + 2))?;
-        let zeros = stuff[0];
-        let harmonic_sum = f32::from_bits(stuff[1]);
-        let registers = stuff.get(2..).unwrap().to_vec();
-
-        assert_eq!(registers.len(), Self::HLL_SLICE_LEN);
-        let mut hll = HyperLogLog::from_registers(registers);
+        let stuff = deserializer.deserialize_seq(TupleU32Visitor(Self::SERIALIZED_LEN))?;
+        let (zeros, harmonic_sum, registers) = Self::split_serialized(&stuff)?;
+        let hll = HyperLogLog::from_registers(registers.to_vec());
 
-        if hll.zeros != zeros {
+        if hll.zeros() != zeros {
             return Err(de::Error::invalid_value(
                 serde::de::Unexpected::Unsigned(zeros.into()),
                 &format!(
                     "zeros to match the zeros from registers ({}) exactly",
-                    hll.zeros
+                    hll.zeros()
                 )
                 .as_str(),
             ));
@@ -159,24 +237,754 @@ impl<'de, const P: usize, const W: usize> Deserialize<'de> for HyperLogLog<P, W>
         // wrong trade-off here.
         //
         // until we fix that properly -- this value is just set *very* high.
-        if (hll.harmonic_sum - harmonic_sum).abs() > 10. {
+        if (hll.harmonic_sum() - f64::from(harmonic_sum)).abs() > 10. {
             return Err(de::Error::invalid_value(
                 serde::de::Unexpected::Float(harmonic_sum.into()),
                 &format!(
                     "harmonic_sum to match computed sum from registers ({}) closely",
-                    hll.harmonic_sum
+                    hll.harmonic_sum()
                 )
                 .as_str(),
             ));
         }
 
-        hll.harmonic_sum = harmonic_sum;
+        let mut hll = hll;
+        let count_word = registers[Self::COUNT_IDX];
+        if !hll.set_count_word(count_word) {
+            return Err(promoted_count_error::<D::Error, P, W>(count_word));
+        }
         Ok(hll)
     }
 }
 
+/// Error for a serialized promotion count that no promotion gives
+fn promoted_count_error<E: de::Error, const P: usize, const W: usize>(count_word: u32) -> E {
+    de::Error::invalid_value(
+        serde::de::Unexpected::Unsigned(count_word.into()),
+        &format!(
+            "promotion count of at most {} elements",
+            HyperLogLog::<P, W>::MAX_PROMOTED_COUNT
+        )
+        .as_str(),
+    )
+}
+
+impl<const P: usize, const W: usize> HyperLogLog<P, W> {
+    /// Length of the serialized `u32` sequence
+    const SERIALIZED_LEN: usize = Self::HLL_SLICE_LEN + 2 + CHECKSUM_LEN;
+
+    /// Split a serialized sequence into zeros, harmonic_sum and registers,
+    /// checking its length and (with the `checksum` feature) its checksum
+    fn split_serialized<E: de::Error>(stuff: &[u32]) -> Result<(u32, f32, &[u32]), E> {
+        let expected_len = Self::SERIALIZED_LEN;
+        #[cfg(feature = "checksum")]
+        let stuff = match stuff.split_last() {
+            Some((&checksum, stuff)) => {
+                let computed = crc32(stuff.get(2..).unwrap_or_default().iter().copied());
+                if checksum != computed {
+                    return Err(de::Error::invalid_value(
+                        serde::de::Unexpected::Unsigned(checksum.into()),
+                        &format!("checksum to match the registers checksum ({computed})").as_str(),
+                    ));
+                }
+                stuff
+            }
+            None => stuff,
+        };
+        let [zeros, harmonic_sum, registers @ ..] = stuff else {
+            return Err(de::Error::invalid_length(
+                stuff.len(),
+                &format!("hyperloglog representation with length {expected_len}").as_str(),
+            ));
+        };
+        if registers.len() != Self::HLL_SLICE_LEN {
+            return Err(de::Error::invalid_length(
+                stuff.len(),
+                &format!("hyperloglog representation with length {expected_len}").as_str(),
+            ));
+        }
+        Ok((*zeros, f32::from_bits(*harmonic_sum), registers))
+    }
+}
+
+/// A `Sketch` with a more compact serialization format
+///
+/// Small, Array and Sparse representations are serialized exactly like `Sketch`. The
+/// HyperLogLog representation is serialized as its `W`-bit registers packed
+/// into a byte blob (`2^P * W / 8` bytes), without the register histogram,
+/// which is recounted on deserialization. When it has a
+/// promotion count (see `HyperLogLog::promoted_count`), the blob starts with
+/// 4 more bytes: `count + 1` as a little-endian `u32`.
+///
+/// This is compact with binary formats that serialize byte blobs directly,
+/// like `postcard` and `bincode`. It is *not* smaller with `serde_json`, which
+/// writes bytes as an array of numbers.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CompactSketch<const P: usize = 12, const W: usize = 6>(pub Sketch<P, W>);
+
+impl<const P: usize, const W: usize> From<Sketch<P, W>> for CompactSketch<P, W> {
+    fn from(sketch: Sketch<P, W>) -> Self {
+        Self(sketch)
+    }
+}
+
+impl<const P: usize, const W: usize> From<CompactSketch<P, W>> for Sketch<P, W> {
+    fn from(compact: CompactSketch<P, W>) -> Self {
+        compact.0
+    }
+}
+
+/// Borrowed mirror of `Sketch` for serializing `CompactSketch`
+#[derive(Serialize)]
+#[serde(rename = "Sketch")]
+enum CompactRef<'a, const P: usize, const W: usize> {
+    #[serde(rename = "s")]
+    Small(&'a Small<P, W>),
+    #[serde(rename = "a")]
+    Array(&'a Array<P, W>),
+    #[serde(rename = "h")]
+    Hll(PackedHllRef<'a, P, W>),
+    #[serde(rename = "p")]
+    Sparse(&'a Sparse<P, W>),
+}
+
+/// Owned mirror of `Sketch` for deserializing `CompactSketch`
+#[derive(Deserialize)]
+#[serde(rename = "Sketch")]
+enum CompactOwned<const P: usize, const W: usize> {
+    #[serde(rename = "s")]
+    Small(Small<P, W>),
+    #[serde(rename = "a")]
+    Array(Array<P, W>),
+    #[serde(rename = "h")]
+    Hll(PackedHll<P, W>),
+    #[serde(rename = "p")]
+    Sparse(Sparse<P, W>),
+}
+
+/// Borrowed mirror of `Sketch` for merging `CompactSketch` bytes, leaving the
+/// packed HyperLogLog registers in place (see `Sketch::merge_from_serialized`)
+#[cfg(feature = "merge_from_serialized")]
+#[derive(Deserialize)]
+#[serde(rename = "Sketch")]
+pub(crate) enum CompactBorrowed<'a, const P: usize, const W: usize> {
+    #[serde(rename = "s")]
+    Small(Small<P, W>),
+    #[serde(rename = "a")]
+    Array(Array<P, W>),
+    #[serde(rename = "h", borrow)]
+    Hll(PackedHllBorrowed<'a, P, W>),
+    #[serde(rename = "p")]
+    Sparse(Sparse<P, W>),
+}
+
+/// Packed HyperLogLog registers borrowed from `CompactSketch` bytes, and the
+/// promotion count stored before them
+#[cfg(feature = "merge_from_serialized")]
+pub(crate) struct PackedHllBorrowed<'a, const P: usize, const W: usize> {
+    pub(crate) count: Option<u32>,
+    pub(crate) registers: HllView<'a, P, W>,
+}
+
+struct PackedHllRef<'a, const P: usize, const W: usize>(&'a HyperLogLog<P, W>);
+
+struct PackedHll<const P: usize, const W: usize>(HyperLogLog<P, W>);
+
+impl<const P: usize, const W: usize> Serialize for CompactSketch<P, W> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match &self.0 {
+            Sketch::Small(small) => CompactRef::Small(small),
+            Sketch::Array(arr) => CompactRef::Array(arr),
+            Sketch::Sparse(sparse) => CompactRef::Sparse(sparse),
+            Sketch::Hll(hll) => CompactRef::Hll(PackedHllRef(hll)),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de, const P: usize, const W: usize> Deserialize<'de> for CompactSketch<P, W> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        Ok(CompactSketch(
+            match CompactOwned::deserialize(deserializer)? {
+                CompactOwned::Small(small) => Sketch::Small(small),
+                CompactOwned::Array(arr) => Sketch::Array(arr),
+                CompactOwned::Sparse(sparse) => Sketch::Sparse(sparse),
+                CompactOwned::Hll(PackedHll(hll)) => Sketch::Hll(hll),
+            },
+        ))
+    }
+}
+
+impl<const P: usize, const W: usize> Serialize for PackedHllRef<'_, P, W> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let count_word = self.0.count_word();
+        if count_word == 0 {
+            return serializer.serialize_bytes(&self.0.to_packed_bytes());
+        }
+        let mut bytes = count_word.to_le_bytes().to_vec();
+        bytes.extend(self.0.to_packed_bytes());
+        serializer.serialize_bytes(&bytes)
+    }
+}
+
+struct PackedBytesVisitor<const P: usize, const W: usize>(PhantomData<HyperLogLog<P, W>>);
+
+impl<const P: usize, const W: usize> PackedBytesVisitor<P, W> {
+    /// Split the serialized promotion count word, 0 when it's not stored, from
+    /// the packed registers
+    fn split_count_word<E: de::Error>(bytes: &[u8]) -> Result<(u32, &[u8]), E> {
+        let expected_len = HyperLogLog::<P, W>::PACKED_LEN;
+        match bytes.len() {
+            len if len == expected_len => Ok((0, bytes)),
+            len if len == expected_len + 4 => {
+                let (count_word, packed) = bytes.split_at(4);
+                let count_word = count_word.try_into().expect("split at 4 bytes");
+                Ok((u32::from_le_bytes(count_word), packed))
+            }
+            len => Err(de::Error::invalid_length(
+                len,
+                &format!("packed hyperloglog registers with length {expected_len}").as_str(),
+            )),
+        }
+    }
+
+    fn unpack<E: de::Error>(bytes: &[u8]) -> Result<PackedHll<P, W>, E> {
+        let (count_word, packed) = Self::split_count_word(bytes)?;
+        let mut hll = HyperLogLog::from_packed_bytes(packed);
+        if !hll.set_count_word(count_word) {
+            return Err(promoted_count_error::<E, P, W>(count_word));
+        }
+        Ok(PackedHll(hll))
+    }
+}
+
+#[cfg(feature = "merge_from_serialized")]
+impl<'de: 'a, 'a, const P: usize, const W: usize> Deserialize<'de> for PackedHllBorrowed<'a, P, W> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let bytes = <&'a [u8]>::deserialize(deserializer)?;
+        let (count_word, packed) = PackedBytesVisitor::<P, W>::split_count_word(bytes)?;
+        if !HyperLogLog::<P, W>::is_valid_count_word(count_word) {
+            return Err(promoted_count_error::<D::Error, P, W>(count_word));
+        }
+        Ok(PackedHllBorrowed {
+            count: HyperLogLog::<P, W>::count_from_word(count_word),
+            registers: HllView::new(packed).expect("length was checked"),
+        })
+    }
+}
+
+impl<'de, const P: usize, const W: usize> Visitor<'de> for PackedBytesVisitor<P, W> {
+    type Value = PackedHll<P, W>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("packed hyperloglog registers")
+    }
+
+    fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<Self::Value, E> {
+        Self::unpack(bytes)
+    }
+
+    fn visit_seq<A>(self, mut access: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        // self-describing formats like json may represent bytes as a sequence
+        let expected_len = HyperLogLog::<P, W>::PACKED_LEN;
+        let max_len = expected_len + 4;
+        let mut bytes = Vec::with_capacity(max_len);
+        while let Some(byte) = access.next_element()? {
+            if bytes.len() == max_len {
+                return Err(de::Error::invalid_length(
+                    max_len + 1,
+                    &format!("packed hyperloglog registers with length {expected_len}").as_str(),
+                ));
+            }
+            bytes.push(byte);
+        }
+        Self::unpack(&bytes)
+    }
+}
+
+impl<'de, const P: usize, const W: usize> Deserialize<'de> for PackedHll<P, W> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_bytes(PackedBytesVisitor(PhantomData))
+    }
+}
+
+/// A `Sketch` deserialized without validating the HyperLogLog representation
+///
+/// Serialized like `Sketch`, except that the HyperLogLog representation is
+/// followed by the `2^W` counts of its registers by rank, the histogram that
+/// estimates are computed from. Deserializing takes them as they are, instead
+/// of rescanning all registers to count them and check the stored zeros and
+/// harmonic_sum, and doesn't check the stored element count against the
+/// limits of promotion either. This makes loading large sketches much cheaper.
+/// Since the formats differ in the HyperLogLog representation, storage must
+/// be written and read with `TrustedSketch` alike.
+///
+/// **Only use this for storage you trust**, that was written by this crate
+/// and can't be tampered with. Corrupted or crafted data is not rejected: a
+/// histogram that doesn't match the registers gives wrong estimates, and
+/// stays wrong through later inserts and merges, which only update it, or
+/// may even make them panic. `Sketch::recompute_cached` repairs it.
+/// Lengths, the total of the histogram (and, with the `checksum` feature, the
+/// checksum of the registers) are still checked.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TrustedSketch<const P: usize = 12, const W: usize = 6>(pub Sketch<P, W>);
+
+impl<const P: usize, const W: usize> From<Sketch<P, W>> for TrustedSketch<P, W> {
+    fn from(sketch: Sketch<P, W>) -> Self {
+        Self(sketch)
+    }
+}
+
+impl<const P: usize, const W: usize> From<TrustedSketch<P, W>> for Sketch<P, W> {
+    fn from(trusted: TrustedSketch<P, W>) -> Self {
+        trusted.0
+    }
+}
+
+/// Owned mirror of `Sketch` for deserializing `TrustedSketch`
+#[derive(Deserialize)]
+#[serde(rename = "Sketch")]
+enum TrustedOwned<const P: usize, const W: usize> {
+    #[serde(rename = "s")]
+    Small(Small<P, W>),
+    #[serde(rename = "a")]
+    Array(Array<P, W>),
+    #[serde(rename = "h")]
+    Hll(TrustedHll<P, W>),
+    #[serde(rename = "p")]
+    Sparse(Sparse<P, W>),
+}
+
+/// Borrowed mirror of `Sketch` for serializing `TrustedSketch`
+#[derive(Serialize)]
+#[serde(rename = "Sketch")]
+enum TrustedRef<'a, const P: usize, const W: usize> {
+    #[serde(rename = "s")]
+    Small(&'a Small<P, W>),
+    #[serde(rename = "a")]
+    Array(&'a Array<P, W>),
+    #[serde(rename = "h")]
+    Hll(TrustedHllRef<'a, P, W>),
+    #[serde(rename = "p")]
+    Sparse(&'a Sparse<P, W>),
+}
+
+struct TrustedHllRef<'a, const P: usize, const W: usize>(&'a HyperLogLog<P, W>);
+
+struct TrustedHll<const P: usize, const W: usize>(HyperLogLog<P, W>);
+
+impl<const P: usize, const W: usize> Serialize for TrustedSketch<P, W> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match &self.0 {
+            Sketch::Small(small) => TrustedRef::Small(small),
+            Sketch::Array(arr) => TrustedRef::Array(arr),
+            Sketch::Sparse(sparse) => TrustedRef::Sparse(sparse),
+            Sketch::Hll(hll) => TrustedRef::Hll(TrustedHllRef(hll)),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<const P: usize, const W: usize> Serialize for TrustedHllRef<'_, P, W> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.0.serialize_with(serializer, &self.0.histogram)
+    }
+}
+
+impl<'de, const P: usize, const W: usize> Deserialize<'de> for TrustedSketch<P, W> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        Ok(TrustedSketch(
+            match TrustedOwned::deserialize(deserializer)? {
+                TrustedOwned::Small(small) => Sketch::Small(small),
+                TrustedOwned::Array(arr) => Sketch::Array(arr),
+                TrustedOwned::Sparse(sparse) => Sketch::Sparse(sparse),
+                TrustedOwned::Hll(TrustedHll(hll)) => Sketch::Hll(hll),
+            },
+        ))
+    }
+}
+
+impl<'de, const P: usize, const W: usize> Deserialize<'de> for TrustedHll<P, W> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let len = HyperLogLog::<P, W>::SERIALIZED_LEN;
+        let stuff = deserializer.deserialize_seq(TupleU32Visitor(len + (1 << W)))?;
+        let (stuff, histogram) = stuff.split_at(len);
+        let (_, _, registers) = HyperLogLog::<P, W>::split_serialized(stuff)?;
+        let total: u64 = histogram.iter().copied().map(u64::from).sum();
+        if total != HyperLogLog::<P, W>::M as u64 {
+            return Err(de::Error::invalid_value(
+                serde::de::Unexpected::Unsigned(total),
+                &format!("register histogram of {} registers", HyperLogLog::<P, W>::M).as_str(),
+            ));
+        }
+        Ok(TrustedHll(HyperLogLog::from_registers_unchecked(
+            registers.to_vec(),
+            histogram.into(),
+        )))
+    }
+}
+
+/// Read-only view of HyperLogLog registers packed by `CompactSketch`
+///
+/// Borrows the packed register bytes (`2^P * W / 8` bytes), for example from
+/// a memory-mapped file of serialized sketches, and estimates from them
+/// without copying the registers. With binary formats like `postcard` and
+/// `bincode`, these are the last bytes of a serialized `CompactSketch` in the
+/// HyperLogLog representation.
+///
+/// Each `estimate` scans all registers, since the register histogram
+/// that `Sketch` keeps is not stored. It also ignores the element count
+/// that a `CompactSketch` may store before its registers (see
+/// `HyperLogLog::promoted_count`), so just after promotion it can be lower
+/// than the sketch's.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HllView<'a, const P: usize = 12, const W: usize = 6>(&'a [u8]);
+
+impl<'a, const P: usize, const W: usize> HllView<'a, P, W> {
+    /// Wrap packed register bytes, or `None` if their length is not `2^P * W / 8`
+    pub fn new(packed: &'a [u8]) -> Option<Self> {
+        (packed.len() == HyperLogLog::<P, W>::PACKED_LEN).then_some(Self(packed))
+    }
+
+    /// Register ranks in index order
+    pub(crate) fn ranks(&self) -> impl Iterator<Item = u32> + '_ {
+        (0..1 << P).map(|idx| self.get_register(idx))
+    }
+
+    /// Get register `idx`, which may straddle two bytes
+    fn get_register(&self, idx: usize) -> u32 {
+        let bit_idx = idx * W;
+        let byte_idx = bit_idx / 8;
+        let lo = self.0[byte_idx];
+        let hi = self.0.get(byte_idx + 1).copied().unwrap_or(0);
+        (u32::from(u16::from_le_bytes([lo, hi])) >> (bit_idx % 8)) & ((1 << W) - 1)
+    }
+
+    /// Compute the estimated cardinality from the registers, same as for the
+    /// owned `Sketch` above its promotion count
+    pub fn estimate(&self) -> usize {
+        let mut histogram = [0; 64];
+        for rank in self.ranks() {
+            histogram[rank as usize] += 1;
+        }
+        // the same histogram as a deserialized `HyperLogLog` keeps
+        let histogram = &histogram[..1 << W];
+        HyperLogLog::<P, W>::estimate_from_histogram(histogram)
+    }
+}
+
+/// A finalized sketch, serialized as just its estimate
+///
+/// For sketches that will never be merged or inserted into again, but are
+/// stored in the same serde stream as mergeable ones: this serializes as a
+/// single integer instead of a whole representation. It answers `estimate`,
+/// and refuses `insert` and `merge` with a `FinalizedError`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct EstimateOnly(u64);
+
+impl EstimateOnly {
+    /// The estimate of the sketch this was made from
+    pub fn estimate(&self) -> usize {
+        self.0 as usize
+    }
+
+    /// Always fails: the elements of a finalized sketch are gone
+    pub fn insert<const P: usize, const W: usize>(
+        &mut self,
+        _element: Element<P, W>,
+    ) -> Result<(), FinalizedError> {
+        Err(FinalizedError)
+    }
+
+    /// Always fails: an estimate can't be merged without double-counting
+    pub fn merge(&mut self, _rhs: &Self) -> Result<(), FinalizedError> {
+        Err(FinalizedError)
+    }
+}
+
+impl<const P: usize, const W: usize> From<&Sketch<P, W>> for EstimateOnly {
+    fn from(sketch: &Sketch<P, W>) -> Self {
+        Self(sketch.estimate() as u64)
+    }
+}
+
+/// Error inserting into or merging an `EstimateOnly`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FinalizedError;
+
+impl fmt::Display for FinalizedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("cannot insert into or merge an estimate-only sketch")
+    }
+}
+
+impl core::error::Error for FinalizedError {}
+
+#[cfg(feature = "hex")]
+impl<const P: usize, const W: usize> Sketch<P, W> {
+    /// Encode this sketch as lowercase hex text
+    ///
+    /// For moving sketches around by hand, like pasting them into a bug report
+    /// or another environment: the text is the `postcard` serialization of a
+    /// `CompactSketch`, so Hll sketches take `2^P * W / 4` characters. Use a
+    /// binary format for high-throughput storage.
+    pub fn to_hex(&self) -> String {
+        const DIGITS: &[u8; 16] = b"0123456789abcdef";
+        let bytes = postcard::to_allocvec(&CompactSketch::from(self.clone()))
+            .expect("serializing to a vec can't fail");
+        bytes
+            .iter()
+            .flat_map(|byte| {
+                [
+                    DIGITS[usize::from(byte >> 4)],
+                    DIGITS[usize::from(byte & 0xf)],
+                ]
+            })
+            .map(char::from)
+            .collect()
+    }
+
+    /// Decode a sketch from hex text made by `to_hex`
+    ///
+    /// Upper- and lowercase hex digits are accepted.
+    pub fn from_hex(s: &str) -> Result<Self, ParseError> {
+        let digit = |offset: usize| {
+            char::from(s.as_bytes()[offset])
+                .to_digit(16)
+                .map(|d| d as u8)
+                .ok_or(ParseError::InvalidHex(offset))
+        };
+        if s.len() % 2 != 0 {
+            return Err(ParseError::InvalidHex(s.len()));
+        }
+        let bytes = (0..s.len())
+            .step_by(2)
+            .map(|offset| Ok((digit(offset)? << 4) | digit(offset + 1)?))
+            .collect::<Result<Vec<u8>, _>>()?;
+        postcard::from_bytes::<CompactSketch<P, W>>(&bytes)
+            .map(Sketch::from)
+            .map_err(|_| ParseError::InvalidSketch)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<const P: usize, const W: usize> Sketch<P, W> {
+    /// Merge every sketch from a stream of serialized buffers into this one
+    ///
+    /// Each buffer is the `postcard` serialization of a `CompactSketch`, the
+    /// bytes `to_hex` encodes. The task yields to the Tokio runtime after
+    /// each merge, so folding a long stream doesn't starve other tasks.
+    ///
+    /// Stops at the first buffer that doesn't deserialize, leaving the
+    /// sketches merged before it in place.
+    pub async fn merge_stream<S>(&mut self, stream: S) -> Result<(), ParseError>
+    where
+        S: futures_core::Stream<Item = Vec<u8>>,
+    {
+        let mut stream = core::pin::pin!(stream);
+        while let Some(buf) = core::future::poll_fn(|cx| stream.as_mut().poll_next(cx)).await {
+            let rhs = postcard::from_bytes::<CompactSketch<P, W>>(&buf)
+                .map_err(|_| ParseError::InvalidSketch)?;
+            self.merge(&rhs.0);
+            tokio::task::yield_now().await;
+        }
+        Ok(())
+    }
+}
+
+/// A `Sketch` serialized with a format version and its `(P, W)` parameters
+///
+/// Plain `Sketch` serialization carries no version tag, and its Small and
+/// Array representations look the same for any parameters, so loading a sketch
+/// with the wrong `P` or `W`, or from a future format, can silently give wrong
+/// estimates. This envelope is serialized as a struct of `version`, `p`, `w`
+/// and the `sketch`, and deserializing it fails unless the version is at most
+/// `VersionedSketch::VERSION` and the parameters match the target type's,
+/// before the sketch itself is read.
+#[cfg(feature = "versioned_serde")]
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct VersionedSketch<const P: usize = 12, const W: usize = 6>(pub Sketch<P, W>);
+
+#[cfg(feature = "versioned_serde")]
+impl<const P: usize, const W: usize> VersionedSketch<P, W> {
+    /// Current version of the serialized format
+    ///
+    /// Version 2 added the Sparse representation. Version 1 sketches are still
+    /// read, since they're valid version 2 sketches.
+    pub const VERSION: u8 = 2;
+
+    const FIELDS: &'static [&'static str] = &["version", "p", "w", "sketch"];
+
+    /// Check the envelope header against this format version and parameters
+    fn check_header<E: de::Error>(version: u8, p: u8, w: u8) -> Result<(), E> {
+        let unexpected = |found: u8, expected: &str| {
+            de::Error::invalid_value(de::Unexpected::Unsigned(found.into()), &expected)
+        };
+        if !(1..=Self::VERSION).contains(&version) {
+            return Err(unexpected(
+                version,
+                &format!("format version 1 to {}", Self::VERSION),
+            ));
+        }
+        if usize::from(p) != P {
+            return Err(unexpected(p, &format!("precision P = {P}")));
+        }
+        if usize::from(w) != W {
+            return Err(unexpected(w, &format!("register width W = {W}")));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "versioned_serde")]
+impl<const P: usize, const W: usize> From<Sketch<P, W>> for VersionedSketch<P, W> {
+    fn from(sketch: Sketch<P, W>) -> Self {
+        Self(sketch)
+    }
+}
+
+#[cfg(feature = "versioned_serde")]
+impl<const P: usize, const W: usize> From<VersionedSketch<P, W>> for Sketch<P, W> {
+    fn from(versioned: VersionedSketch<P, W>) -> Self {
+        versioned.0
+    }
+}
+
+#[cfg(feature = "versioned_serde")]
+impl<const P: usize, const W: usize> Serialize for VersionedSketch<P, W> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        use serde::ser::SerializeStruct;
+
+        let mut s = serializer.serialize_struct("VersionedSketch", Self::FIELDS.len())?;
+        s.serialize_field("version", &Self::VERSION)?;
+        s.serialize_field("p", &(P as u8))?;
+        s.serialize_field("w", &(W as u8))?;
+        s.serialize_field("sketch", &self.0)?;
+        s.end()
+    }
+}
+
+#[cfg(feature = "versioned_serde")]
+#[derive(Deserialize)]
+#[serde(field_identifier, rename_all = "lowercase")]
+enum VersionedField {
+    Version,
+    P,
+    W,
+    Sketch,
+}
+
+#[cfg(feature = "versioned_serde")]
+struct VersionedVisitor<const P: usize, const W: usize>;
+
+#[cfg(feature = "versioned_serde")]
+impl<'de, const P: usize, const W: usize> Visitor<'de> for VersionedVisitor<P, W> {
+    type Value = VersionedSketch<P, W>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a versioned sketch")
+    }
+
+    fn visit_seq<A>(self, mut access: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut next_header = |i| {
+            access
+                .next_element::<u8>()?
+                .ok_or_else(|| de::Error::invalid_length(i, &self))
+        };
+        let (version, p, w) = (next_header(0)?, next_header(1)?, next_header(2)?);
+        VersionedSketch::<P, W>::check_header(version, p, w)?;
+        let sketch = access
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(3, &self))?;
+        Ok(VersionedSketch(sketch))
+    }
+
+    fn visit_map<A>(self, mut access: A) -> Result<Self::Value, A::Error>
+    where
+        A: de::MapAccess<'de>,
+    {
+        let (mut version, mut p, mut w) = (None, None, None);
+        let mut sketch = None;
+        while let Some(field) = access.next_key()? {
+            let (slot, name) = match field {
+                VersionedField::Version => (&mut version, "version"),
+                VersionedField::P => (&mut p, "p"),
+                VersionedField::W => (&mut w, "w"),
+                VersionedField::Sketch => {
+                    if sketch.is_some() {
+                        return Err(de::Error::duplicate_field("sketch"));
+                    }
+                    // the header must be checked before reading the sketch
+                    let version = version.ok_or(de::Error::missing_field("version"))?;
+                    let p = p.ok_or(de::Error::missing_field("p"))?;
+                    let w = w.ok_or(de::Error::missing_field("w"))?;
+                    VersionedSketch::<P, W>::check_header(version, p, w)?;
+                    sketch = Some(access.next_value()?);
+                    continue;
+                }
+            };
+            if slot.is_some() {
+                return Err(de::Error::duplicate_field(name));
+            }
+            *slot = Some(access.next_value()?);
+        }
+        sketch
+            .map(VersionedSketch)
+            .ok_or(de::Error::missing_field("sketch"))
+    }
+}
+
+#[cfg(feature = "versioned_serde")]
+impl<'de, const P: usize, const W: usize> Deserialize<'de> for VersionedSketch<P, W> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_struct("VersionedSketch", Self::FIELDS, VersionedVisitor)
+    }
+}
+
 #[cfg(test)]
 pub mod tests {
+    use super::{CompactSketch, EstimateOnly, FinalizedError, HllView, TrustedSketch};
+    use crate::hyperloglog::HyperLogLog;
     use crate::{Element, Sketch};
     use test_case::test_case;
     use wyhash::WyHash;
@@ -185,6 +993,7 @@ pub mod tests {
     #[test_case(1; "single element")]
     #[test_case(2; "two distinct elements")]
     #[test_case(100; "hundred distinct elements")]
+    #[test_case(500; "five hundred distinct elements")]
     #[test_case(10000; "ten thousand distinct elements")]
     fn test_serde(n: usize) {
         let mut original_estimator = Sketch::default();
@@ -200,9 +1009,8 @@ pub mod tests {
             "serialized string should not be empty"
         );
 
-        let deserialized_estimator: Sketch = serde_json::from_str::<Sketch>(&serialized)
-            .expect("deserialization failed")
-            .into();
+        let deserialized_estimator: Sketch =
+            serde_json::from_str::<Sketch>(&serialized).expect("deserialization failed");
 
         assert_eq!(original_estimator, deserialized_estimator);
 
@@ -215,11 +1023,19 @@ pub mod tests {
             "postcard_serialized bytes should not be empty"
         );
 
-        let postcard_estimator: Sketch = postcard::from_bytes::<Sketch>(&postcard_serialized)
-            .expect("deserialization failed")
-            .into();
+        let postcard_estimator: Sketch =
+            postcard::from_bytes::<Sketch>(&postcard_serialized).expect("deserialization failed");
 
         assert_eq!(original_estimator, postcard_estimator);
+
+        // and with bincode
+
+        let bincode_serialized =
+            bincode::serialize(&original_estimator).expect("serialization failed");
+        let bincode_estimator: Sketch =
+            bincode::deserialize(&bincode_serialized).expect("deserialization failed");
+
+        assert_eq!(original_estimator, bincode_estimator);
     }
 
     #[test]
@@ -244,4 +1060,485 @@ pub mod tests {
         let result: Result<Sketch, _> = postcard::from_bytes(input);
         assert!(result.is_err());
     }
+
+    #[test_case(vec![5, 3, 4]; "too short")]
+    #[test_case((0..1000).collect(); "too long")]
+    #[test_case(vec![4; 200]; "duplicates")]
+    #[test_case((0..200).rev().collect(); "descending")]
+    fn test_deserialize_invalid_sparse(items: Vec<u32>) {
+        let json = serde_json::json!({ "p": items }).to_string();
+        assert!(serde_json::from_str::<Sketch>(&json).is_err());
+
+        // only the length limits still apply once sorted and deduplicated
+        let mut sorted = items;
+        sorted.sort_unstable();
+        sorted.dedup();
+        let json = serde_json::json!({ "p": sorted }).to_string();
+        let result = serde_json::from_str::<Sketch>(&json);
+        let valid = (129..=super::Sparse::<12, 6>::MAX_LEN).contains(&sorted.len());
+        assert_eq!(result.is_ok(), valid, "{result:?}");
+    }
+
+    #[test_case(0; "empty")]
+    #[test_case(1; "zeros only")]
+    #[test_case(2; "no registers")]
+    #[test_case(3; "one register")]
+    #[test_case(HyperLogLog::<12, 6>::HLL_SLICE_LEN + 1; "one register short")]
+    #[test_case(HyperLogLog::<12, 6>::HLL_SLICE_LEN + 3; "one register long")]
+    fn test_deserialize_truncated_hll(len: usize) {
+        let mut stuff = vec![0u32; len];
+        if let Some(zeros) = stuff.first_mut() {
+            *zeros = 1 << 12;
+        }
+
+        let json = serde_json::json!({ "h": stuff }).to_string();
+        let result = std::panic::catch_unwind(|| serde_json::from_str::<Sketch<12, 6>>(&json));
+        assert!(result.expect("deserialization must not panic").is_err());
+
+        let bytes = postcard::to_allocvec(&(2u8, stuff)).unwrap();
+        let result = std::panic::catch_unwind(|| postcard::from_bytes::<Sketch<12, 6>>(&bytes));
+        assert!(result.expect("deserialization must not panic").is_err());
+    }
+
+    #[cfg(feature = "checksum")]
+    #[test_case(0; "first register")]
+    #[test_case(500; "middle register")]
+    #[test_case(HyperLogLog::<12, 6>::HLL_SLICE_LEN; "checksum")]
+    fn test_checksum_rejects_bit_flip(word: usize) {
+        let sketch: Sketch<12, 6> = (0..10_000usize)
+            .map(Element::from_hasher_default::<WyHash>)
+            .collect();
+        let mut bytes = bincode::serialize(&sketch).unwrap();
+        assert_eq!(
+            bincode::deserialize::<Sketch<12, 6>>(&bytes).unwrap(),
+            sketch
+        );
+
+        // bincode layout: u32 variant tag, u64 seq length, then u32 zeros and harmonic_sum
+        let offset = 4 + 8 + 2 * 4 + word * 4;
+        bytes[offset] ^= 0b100;
+        let err = bincode::deserialize::<Sketch<12, 6>>(&bytes).unwrap_err();
+        assert!(err.to_string().contains("checksum"), "{err}");
+    }
+
+    #[test_case(0; "empty set")]
+    #[test_case(2; "two distinct elements")]
+    #[test_case(100; "hundred distinct elements")]
+    #[test_case(500; "five hundred distinct elements")]
+    #[test_case(10000; "ten thousand distinct elements")]
+    fn test_compact_serde(n: usize) {
+        let sketch: Sketch = (0..n).map(Element::from_hasher_default::<WyHash>).collect();
+        let compact = CompactSketch(sketch.clone());
+
+        let json = serde_json::to_string(&compact).expect("serialization failed");
+        let from_json: CompactSketch = serde_json::from_str(&json).expect("deserialization failed");
+        assert_eq!(from_json, compact);
+
+        let bincode_bytes = bincode::serialize(&compact).expect("serialization failed");
+        let from_bincode: CompactSketch =
+            bincode::deserialize(&bincode_bytes).expect("deserialization failed");
+        assert_eq!(from_bincode, compact);
+
+        let bytes = postcard::to_allocvec(&compact).expect("serialization failed");
+        let from_bytes: CompactSketch =
+            postcard::from_bytes(&bytes).expect("deserialization failed");
+        assert_eq!(from_bytes, compact);
+        assert_eq!(Sketch::from(from_bytes).estimate(), sketch.estimate());
+
+        let regular_bytes = postcard::to_allocvec(&sketch).expect("serialization failed");
+        if let Sketch::Hll(_) = sketch {
+            // 4096 registers * 6 bits, plus tag and length prefix
+            assert_eq!(bytes.len(), 3072 + 3);
+            assert!(bytes.len() < regular_bytes.len());
+        } else {
+            assert_eq!(bytes, regular_bytes);
+            assert_eq!(json, serde_json::to_string(&sketch).unwrap());
+        }
+    }
+
+    #[cfg(feature = "versioned_serde")]
+    #[test_case(0; "empty set")]
+    #[test_case(2; "two distinct elements")]
+    #[test_case(100; "hundred distinct elements")]
+    #[test_case(500; "five hundred distinct elements")]
+    #[test_case(10000; "ten thousand distinct elements")]
+    fn test_versioned_serde(n: usize) {
+        use super::VersionedSketch;
+
+        let sketch: Sketch = (0..n).map(Element::from_hasher_default::<WyHash>).collect();
+        let versioned = VersionedSketch(sketch.clone());
+
+        let json = serde_json::to_string(&versioned).expect("serialization failed");
+        assert!(
+            json.starts_with(r#"{"version":2,"p":12,"w":6,"sketch":"#),
+            "{json}"
+        );
+        let from_json: VersionedSketch =
+            serde_json::from_str(&json).expect("deserialization failed");
+        assert_eq!(from_json, versioned);
+        assert_eq!(Sketch::from(from_json).estimate(), sketch.estimate());
+
+        let bytes = postcard::to_allocvec(&versioned).expect("serialization failed");
+        assert_eq!(bytes[..3], [2, 12, 6]);
+        let from_bytes: VersionedSketch =
+            postcard::from_bytes(&bytes).expect("deserialization failed");
+        assert_eq!(from_bytes, versioned);
+
+        // version 1 predates `Sparse`, but its representations are unchanged
+        if !matches!(sketch, Sketch::Sparse(_)) {
+            let json = json.replacen(r#""version":2"#, r#""version":1"#, 1);
+            assert_eq!(
+                serde_json::from_str::<VersionedSketch>(&json).unwrap(),
+                versioned
+            );
+        }
+    }
+
+    #[cfg(feature = "versioned_serde")]
+    #[test_case(r#"{"version":3,"p":12,"w":6,"sketch":{"s":0}}"#, "format version 1 to 2"; "version")]
+    #[test_case(r#"{"version":1,"p":10,"w":6,"sketch":{"s":0}}"#, "precision P = 12"; "precision")]
+    #[test_case(r#"{"version":1,"p":12,"w":5,"sketch":{"s":0}}"#, "register width W = 6"; "width")]
+    #[test_case(r#"{"version":1,"p":12,"sketch":{"s":0}}"#, "missing field `w`"; "missing header")]
+    #[test_case(r#"{"version":1,"p":12,"w":6}"#, "missing field `sketch`"; "missing sketch")]
+    fn test_versioned_rejects_json(json: &str, message: &str) {
+        let err = serde_json::from_str::<super::VersionedSketch>(json).unwrap_err();
+        assert!(err.to_string().contains(message), "{err}");
+    }
+
+    #[cfg(feature = "versioned_serde")]
+    #[test]
+    fn test_versioned_rejects_mismatched_params() {
+        use super::VersionedSketch;
+
+        // an Hll sketch has a different length for other parameters, but the
+        // header is rejected before trying to read it
+        let sketch: Sketch<10, 6> = (0..10_000usize)
+            .map(Element::from_hasher_default::<WyHash>)
+            .collect();
+        let versioned = VersionedSketch(sketch);
+        let mut bytes = bincode::serialize(&versioned).unwrap();
+        assert_eq!(
+            bincode::deserialize::<VersionedSketch<10, 6>>(&bytes).unwrap(),
+            versioned
+        );
+        let err = bincode::deserialize::<VersionedSketch<12, 6>>(&bytes).unwrap_err();
+        assert!(err.to_string().contains("precision P = 12"), "{err}");
+        let err = bincode::deserialize::<VersionedSketch<10, 5>>(&bytes).unwrap_err();
+        assert!(err.to_string().contains("register width W = 5"), "{err}");
+
+        bytes[0] = 3;
+        let err = bincode::deserialize::<VersionedSketch<10, 6>>(&bytes).unwrap_err();
+        assert!(err.to_string().contains("format version 1 to 2"), "{err}");
+    }
+
+    #[test_case(3; "smallest array")]
+    #[test_case(16; "inline with smallvec")]
+    #[test_case(17; "spilled with smallvec")]
+    #[test_case(128; "largest array")]
+    fn test_array_storage_is_unobservable(n: usize) {
+        let sketch: Sketch = (0..n).map(Element::from_hasher_default::<WyHash>).collect();
+        assert_eq!(sketch.estimate(), n);
+        let items: Vec<u32> = sketch.iter_sparse().unwrap().collect();
+
+        // variant index of the Array representation, then its items
+        let bytes = postcard::to_allocvec(&sketch).expect("serialization failed");
+        assert_eq!(bytes, postcard::to_allocvec(&(1u8, &items)).unwrap());
+        let deserialized: Sketch = postcard::from_bytes(&bytes).expect("deserialization failed");
+        assert_eq!(deserialized, sketch);
+        assert_eq!(deserialized.estimate(), n);
+    }
+
+    #[test_case(0; "empty set")]
+    #[test_case(2; "two distinct elements")]
+    #[test_case(100; "hundred distinct elements")]
+    #[test_case(500; "five hundred distinct elements")]
+    #[test_case(10000; "ten thousand distinct elements")]
+    fn test_trusted_serde(n: usize) {
+        let sketch: Sketch = (0..n).map(Element::from_hasher_default::<WyHash>).collect();
+        let bytes = bincode::serialize(&sketch).expect("serialization failed");
+        let trusted_bytes = bincode::serialize(&TrustedSketch(sketch.clone())).unwrap();
+        // only the Hll representation is followed by its histogram
+        match &sketch {
+            Sketch::Hll(_) => assert_eq!(trusted_bytes.len(), bytes.len() + 64 * 4),
+            _ => assert_eq!(trusted_bytes, bytes),
+        }
+
+        let checked: Sketch = bincode::deserialize(&bytes).expect("deserialization failed");
+        let trusted: TrustedSketch =
+            bincode::deserialize(&trusted_bytes).expect("deserialization failed");
+        assert_eq!(trusted.0, checked);
+        assert_eq!(trusted.0.estimate_f64(), checked.estimate_f64());
+        if let (Sketch::Hll(trusted), Sketch::Hll(checked)) = (&trusted.0, &checked) {
+            assert_eq!(trusted.histogram, checked.histogram);
+        }
+
+        let json = serde_json::to_string(&TrustedSketch(sketch)).expect("serialization failed");
+        let trusted: TrustedSketch = serde_json::from_str(&json).expect("deserialization failed");
+        assert_eq!(trusted.0.estimate_f64(), checked.estimate_f64());
+    }
+
+    #[test]
+    fn test_trusted_skips_validation() {
+        let sketch: Sketch<12, 6> = (0..10_000usize)
+            .map(Element::from_hasher_default::<WyHash>)
+            .collect();
+        let mut bytes = bincode::serialize(&TrustedSketch(sketch.clone())).unwrap();
+
+        // bincode layout: u32 variant tag, u64 seq length, then u32 zeros
+        bytes[4 + 8] ^= 0b100;
+        let trusted: TrustedSketch<12, 6> = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(trusted.0, sketch);
+
+        // a histogram that doesn't match the registers is taken as it is
+        let last = bytes.len() - 64 * 4;
+        let mut moved = bytes.clone();
+        moved[last] -= 1;
+        moved[last + 4] += 1;
+        let trusted: TrustedSketch<12, 6> = bincode::deserialize(&moved).unwrap();
+        assert_ne!(trusted.0.estimate(), sketch.estimate());
+        let mut repaired = trusted.0;
+        repaired.recompute_cached();
+        assert_eq!(repaired.estimate(), sketch.estimate());
+
+        // but its total is still checked, and so are lengths
+        moved[last] += 1;
+        assert!(bincode::deserialize::<TrustedSketch<12, 6>>(&moved).is_err());
+        bytes.truncate(bytes.len() - 4);
+        assert!(bincode::deserialize::<TrustedSketch<12, 6>>(&bytes).is_err());
+    }
+
+    #[test_case(1_000; "hll")]
+    #[test_case(100_000; "dense")]
+    #[test_case(10_000_000; "saturated")]
+    fn test_hll_view(n: usize) {
+        let sketch: Sketch<12, 4> = (0..n).map(Element::from_hasher_default::<WyHash>).collect();
+        let bytes = postcard::to_allocvec(&CompactSketch(sketch.clone())).unwrap();
+
+        let packed = &bytes[bytes.len() - HyperLogLog::<12, 4>::PACKED_LEN..];
+        let view = HllView::<12, 4>::new(packed).unwrap();
+        let owned: CompactSketch<12, 4> = postcard::from_bytes(&bytes).unwrap();
+        assert_eq!(view.estimate(), owned.0.estimate());
+        assert_eq!(view.estimate(), sketch.estimate());
+
+        assert!(HllView::<12, 4>::new(&packed[1..]).is_none());
+        assert!(HllView::<12, 4>::new(&bytes).is_none());
+    }
+
+    #[test_case(0, 0..15_000; "hll into empty")]
+    #[test_case(2, 5_000..20_000; "hll into small")]
+    #[test_case(100, 5_000..20_000; "hll into array")]
+    #[test_case(500, 5_000..20_000; "hll into sparse")]
+    #[test_case(10_000, 5_000..20_000; "hll into hll")]
+    #[test_case(10_000, 0..773; "just promoted hll into hll")]
+    #[test_case(10_000, 0..2; "small into hll")]
+    #[test_case(100, 50..150; "array into array")]
+    #[test_case(10_000, 5_000..5_500; "sparse into hll")]
+    #[cfg(feature = "merge_from_serialized")]
+    fn test_merge_from_serialized(lhs_n: usize, rhs: core::ops::Range<usize>) {
+        let rhs: Sketch = rhs.map(Element::from_hasher_default::<WyHash>).collect();
+        let bytes = postcard::to_allocvec(&CompactSketch(rhs)).unwrap();
+        let lhs: Sketch = (0..lhs_n)
+            .map(Element::from_hasher_default::<WyHash>)
+            .collect();
+
+        let mut expected = lhs.clone();
+        let deserialized: CompactSketch = postcard::from_bytes(&bytes).unwrap();
+        expected.merge(&deserialized.0);
+
+        let mut merged = lhs.clone();
+        merged.merge_from_serialized(&bytes).unwrap();
+        assert_eq!(merged, expected);
+        assert_eq!(merged.estimate(), expected.estimate());
+
+        let mut unchanged = lhs.clone();
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(
+            unchanged.merge_from_serialized(&trailing),
+            Err(crate::ParseError::InvalidSketch)
+        );
+        assert_eq!(
+            unchanged.merge_from_serialized(&bytes[..bytes.len() - 1]),
+            Err(crate::ParseError::InvalidSketch)
+        );
+        assert_eq!(unchanged, lhs);
+    }
+
+    #[test_case(vec![0u8; 3071]; "one byte short")]
+    #[test_case([&u32::MAX.to_le_bytes()[..], &[0u8; 3072]].concat(); "invalid count")]
+    #[cfg(feature = "merge_from_serialized")]
+    fn test_merge_from_serialized_invalid_hll(packed: Vec<u8>) {
+        let mut sketch: Sketch = (0..100usize)
+            .map(Element::from_hasher_default::<WyHash>)
+            .collect();
+        let expected = sketch.clone();
+        let bytes = postcard::to_allocvec(&(2u8, packed)).unwrap();
+        assert_eq!(
+            sketch.merge_from_serialized(&bytes),
+            Err(crate::ParseError::InvalidSketch)
+        );
+        assert_eq!(sketch, expected);
+    }
+
+    #[test_case(0; "empty")]
+    #[test_case(3071; "one byte short")]
+    #[test_case(3073; "one byte long")]
+    fn test_compact_wrong_length(len: usize) {
+        let bytes = postcard::to_allocvec(&(2u8, vec![0u8; len])).unwrap();
+        assert!(postcard::from_bytes::<CompactSketch>(&bytes).is_err());
+
+        let json = serde_json::json!({ "h": vec![0u8; len] }).to_string();
+        assert!(serde_json::from_str::<CompactSketch>(&json).is_err());
+    }
+
+    /// Iterator with a deliberately wrong `size_hint`
+    struct MisleadingHint<I>(I, usize);
+
+    impl<I: Iterator> Iterator for MisleadingHint<I> {
+        type Item = I::Item;
+
+        fn next(&mut self) -> Option<I::Item> {
+            self.0.next()
+        }
+
+        fn size_hint(&self) -> (usize, Option<usize>) {
+            (self.1, Some(self.1))
+        }
+    }
+
+    #[test_case(0; "no hint")]
+    #[test_case(10; "wrong hint")]
+    fn test_hll_ignores_size_hint(hint: usize) {
+        use serde::de::value::{Error, SeqDeserializer};
+        use serde::Deserialize;
+
+        let sketch: Sketch = (0..10000)
+            .map(Element::from_hasher_default::<WyHash>)
+            .collect();
+        let Sketch::Hll(hll) = sketch else {
+            panic!("expected hll representation");
+        };
+        let stuff: Vec<u32> = serde_json::from_value(serde_json::to_value(&hll).unwrap()).unwrap();
+
+        let seq = SeqDeserializer::<_, Error>::new(MisleadingHint(stuff.iter().copied(), hint));
+        assert_eq!(HyperLogLog::deserialize(seq).unwrap(), hll);
+
+        let long = stuff.iter().copied().chain([0]);
+        let seq = SeqDeserializer::<_, Error>::new(MisleadingHint(long, hint));
+        assert!(HyperLogLog::<12, 6>::deserialize(seq).is_err());
+    }
+
+    #[test_case(0; "empty")]
+    #[test_case(100; "array")]
+    #[test_case(10_000; "hll")]
+    fn test_estimate_only(n: usize) {
+        let sketch: Sketch = (0..n).map(Element::from_hasher_default::<WyHash>).collect();
+        let finalized = EstimateOnly::from(&sketch);
+        assert_eq!(finalized.estimate(), sketch.estimate());
+
+        let json = serde_json::to_string(&finalized).unwrap();
+        assert_eq!(json, sketch.estimate().to_string());
+        assert_eq!(
+            serde_json::from_str::<EstimateOnly>(&json).unwrap(),
+            finalized
+        );
+
+        let bytes = postcard::to_allocvec(&finalized).unwrap();
+        assert!(bytes.len() <= 3);
+        assert_eq!(
+            postcard::from_bytes::<EstimateOnly>(&bytes).unwrap(),
+            finalized
+        );
+    }
+
+    #[test]
+    fn test_estimate_only_refuses_updates() {
+        let mut finalized = EstimateOnly::from(&Sketch::<12, 6>::default());
+        let element = Element::<12, 6>::from_hasher_default::<WyHash>(1);
+        assert_eq!(finalized.insert(element), Err(FinalizedError));
+        assert_eq!(
+            finalized.merge(&EstimateOnly::default()),
+            Err(FinalizedError)
+        );
+        assert_eq!(finalized.estimate(), 0);
+    }
+
+    #[cfg(feature = "hex")]
+    #[test_case(0; "empty")]
+    #[test_case(2; "small")]
+    #[test_case(100; "array")]
+    #[test_case(10_000; "hll")]
+    fn test_hex(n: usize) {
+        let sketch: Sketch = (0..n).map(Element::from_hasher_default::<WyHash>).collect();
+        let hex = sketch.to_hex();
+        assert!(hex
+            .bytes()
+            .all(|c| c.is_ascii_digit() || (b'a'..=b'f').contains(&c)));
+        assert_eq!(Sketch::from_hex(&hex), Ok(sketch.clone()));
+        assert_eq!(Sketch::from_hex(&hex.to_uppercase()), Ok(sketch));
+    }
+
+    #[cfg(feature = "hex")]
+    #[test_case("0" => Err(crate::ParseError::InvalidHex(1)); "odd length")]
+    #[test_case("0g" => Err(crate::ParseError::InvalidHex(1)); "not a digit")]
+    #[test_case("é00" => Err(crate::ParseError::InvalidHex(0)); "not ascii")]
+    #[test_case("" => Err(crate::ParseError::InvalidSketch); "empty")]
+    #[test_case("ff" => Err(crate::ParseError::InvalidSketch); "unknown variant")]
+    fn test_hex_malformed(s: &str) -> Result<Sketch, crate::ParseError> {
+        Sketch::from_hex(s)
+    }
+
+    /// A stream yielding the items of an iterator, always ready
+    #[cfg(feature = "tokio")]
+    struct IterStream<I>(I);
+
+    #[cfg(feature = "tokio")]
+    impl<I: Iterator + Unpin> futures_core::Stream for IterStream<I> {
+        type Item = I::Item;
+
+        fn poll_next(
+            mut self: core::pin::Pin<&mut Self>,
+            _: &mut core::task::Context<'_>,
+        ) -> core::task::Poll<Option<I::Item>> {
+            core::task::Poll::Ready(self.0.next())
+        }
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_merge_stream() {
+        let sketches: Vec<Sketch> = [0..2, 1..100, 50..5_000, 4_000..4_002]
+            .into_iter()
+            .map(|range| range.map(Element::from_hasher_default::<WyHash>).collect())
+            .collect();
+        let buffers: Vec<Vec<u8>> = (sketches.iter())
+            .map(|sketch| {
+                postcard::to_allocvec(&CompactSketch::from(sketch.clone()))
+                    .expect("serialization failed")
+            })
+            .collect();
+
+        let mut streamed = Sketch::default();
+        streamed
+            .merge_stream(IterStream(buffers.into_iter()))
+            .await
+            .unwrap();
+        let folded = sketches.iter().fold(Sketch::default(), |mut acc, sketch| {
+            acc.merge(sketch);
+            acc
+        });
+        assert_eq!(streamed, folded);
+        assert_eq!(streamed.estimate(), folded.estimate());
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_merge_stream_invalid() {
+        let valid = postcard::to_allocvec(&CompactSketch::<12, 6>::default()).unwrap();
+        let mut sketch = Sketch::<12, 6>::default();
+        let result = sketch
+            .merge_stream(IterStream([valid, vec![0xff]].into_iter()))
+            .await;
+        assert_eq!(result, Err(crate::ParseError::InvalidSketch));
+    }
 }
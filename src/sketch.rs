@@ -1,21 +1,41 @@
+#[cfg(feature = "merge_from_serialized")]
+use crate::serde::{CompactBorrowed, PackedHllBorrowed};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::iter::Sum;
+use core::mem::size_of;
+use core::ops::{Add, AddAssign, Deref};
+#[cfg(feature = "with_digest")]
+use digest::Digest;
 use enum_dispatch::enum_dispatch;
-use std::ops::Deref;
 
-use crate::array::Array;
+use crate::array::{Array, MAX_CAPACITY as ARRAY_MAX_CAPACITY};
 use crate::element::Element;
 use crate::hyperloglog::HyperLogLog;
+#[cfg(feature = "minhash")]
+use crate::minhash::{MinHash, MINHASH_K};
 use crate::small::Small;
+use crate::sparse::Sparse;
+#[cfg(feature = "theta")]
+use crate::theta::{self, THETA_K};
+#[cfg(feature = "theta")]
+use alloc::borrow::Cow;
 #[cfg(feature = "with_serde")]
 use serde::{Deserialize, Serialize};
 
 /// Cardinality-estimating sketch data
 ///
-/// Three different representations are used:
+/// Four different representations are used:
 /// - `Small` encodes zero, one, or two elements in a single `u64`
 /// - `Array` is a sparse representation for cardinalities up to 128
+/// - `Sparse` keeps sorted encoded hashes for higher cardinalities, until
+///   they would take more memory than the HyperLogLog registers (771
+///   elements for `P = 12, W = 6`). Sketches with `P` below 10 skip it.
 /// - `Hll` is the actual HyperLogLog sketch, used for higher cardinalities
 #[repr(u8)]
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "with_serde", derive(Serialize, Deserialize))]
 #[enum_dispatch]
 #[allow(private_interfaces)]
@@ -26,6 +46,137 @@ pub enum Sketch<const P: usize = 12, const W: usize = 6> {
     Array(Array<P, W>),
     #[cfg_attr(feature = "with_serde", serde(rename = "h"))]
     Hll(HyperLogLog<P, W>),
+    // declared last, keeping the serialized variant indices of the others
+    #[cfg_attr(feature = "with_serde", serde(rename = "p"))]
+    Sparse(Sparse<P, W>),
+}
+
+/// Kind of representation a `Sketch` is using, without its contents
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RepresentationKind {
+    /// Inline storage for up to two elements
+    Small,
+    /// Sparse storage of encoded hashes for up to 128 elements
+    Array,
+    /// Sorted storage of encoded hashes, up to the size of the registers or
+    /// 1024 elements
+    Sparse,
+    /// Dense HyperLogLog registers
+    Hll,
+}
+
+/// How far an estimate can be trusted, see `Sketch::estimate_checked`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Confidence {
+    /// Counted exactly by the Small, Array or Sparse representation (up to
+    /// hash collisions)
+    Exact,
+    /// HyperLogLog estimate within its usual standard error
+    Normal,
+    /// HyperLogLog estimate above `Sketch::max_reliable_cardinality`, where
+    /// enough registers saturate that accuracy degrades
+    Degraded,
+}
+
+/// Error inserting into a sketch when upgrading it would exceed a memory budget
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CapacityError {
+    /// Bytes the sketch would use after upgrading (see `Sketch::hll_size_of`)
+    pub required: usize,
+    /// The caller's memory budget in bytes
+    pub budget: usize,
+}
+
+impl fmt::Display for CapacityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "upgrading to the Hll representation needs {} bytes, over the budget of {} bytes",
+            self.required, self.budget
+        )
+    }
+}
+
+impl core::error::Error for CapacityError {}
+
+/// Error loading HyperLogLog registers with `Sketch::load_hll_registers`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadError {
+    /// The registers slice doesn't have the expected number of `u32` words
+    InvalidLength { expected: usize, found: usize },
+    /// A register holds a rank that no hash can produce for these `P` and `W`
+    InvalidRank { index: usize, rank: u8 },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::InvalidLength { expected, found } => write!(
+                f,
+                "cannot load {found} register words, expected {expected} words"
+            ),
+            LoadError::InvalidRank { index, rank } => {
+                write!(f, "register {index} holds impossible rank {rank}")
+            }
+        }
+    }
+}
+
+impl core::error::Error for LoadError {}
+
+/// Error inserting packed hashes whose byte length isn't a multiple of 8
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HashBytesError {
+    /// Length of the rejected buffer in bytes
+    pub len: usize,
+}
+
+impl fmt::Display for HashBytesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} bytes are not a whole number of 8-byte hashes",
+            self.len
+        )
+    }
+}
+
+impl core::error::Error for HashBytesError {}
+
+/// Error parsing a sketch with `Sketch::from_hex`, `Sketch::from_small_word`,
+/// `Sketch::merge_stream` or `Sketch::merge_from_serialized`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    /// The text has an odd length, or a non-hex-digit character at this byte offset
+    InvalidHex(usize),
+    /// The bytes are not a sketch with these `P` and `W` parameters
+    InvalidSketch,
+    /// The word is not a Small representation holding zero, one or two
+    /// distinct encoded hashes
+    InvalidSmallWord(u64),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::InvalidHex(offset) => write!(f, "invalid hex at offset {offset}"),
+            ParseError::InvalidSketch => f.write_str("bytes do not decode to a valid sketch"),
+            ParseError::InvalidSmallWord(data) => {
+                write!(f, "{data:#018x} is not a valid Small representation word")
+            }
+        }
+    }
+}
+
+impl core::error::Error for ParseError {}
+
+/// Re-encode a sparse hash from register width `W2` to width `W`
+///
+/// The sparse index keeps `32 - W - 1` bits, so it's truncated for wider `W`.
+fn widen_encoded<const W2: usize, const W: usize>(h: u32) -> u32 {
+    let rank = h & ((1 << W2) - 1);
+    let idx = (h >> W2) & ((1 << (32 - W - 1)) - 1);
+    (idx << W) | rank
 }
 
 /// Sketch trait which must be implemented by all representations.
@@ -33,8 +184,14 @@ pub enum Sketch<const P: usize = 12, const W: usize = 6> {
 pub(crate) trait SketchTrait<const P: usize, const W: usize> {
     fn insert_encoded_hash(&mut self, h: u32) -> Option<Sketch<P, W>>;
     fn estimate_sketch(&self) -> usize;
+    /// Unrounded estimate, for sparse representations their exact count
+    fn estimate_sketch_f64(&self) -> f64 {
+        self.estimate_sketch() as f64
+    }
+    fn is_empty_sketch(&self) -> bool;
     #[allow(dead_code)]
     fn size_of(&self) -> usize;
+    fn heap_size(&self) -> usize;
     fn to_string(&self) -> String {
         format!("estimate: {}", self.estimate_sketch())
     }
@@ -48,15 +205,601 @@ impl<const P: usize, const W: usize> Sketch<P, W> {
         self.insert_encoded(element.0)
     }
 
+    /// Insert element bytes hashed by any `Digest` hasher
+    ///
+    /// Shorthand for inserting `Element::from_digest_oneshot`.
+    #[cfg(feature = "with_digest")]
+    pub fn insert_digest<D: Digest>(&mut self, element: impl AsRef<[u8]>) {
+        self.insert(Element::from_digest_oneshot::<D>(element))
+    }
+
+    /// Insert element bytes with a secret prefix hashed by any `Digest` hasher
+    ///
+    /// Shorthand for inserting `Element::from_digest_with_prefix`: see there
+    /// for why the prefix must never change for a sketch.
+    #[cfg(feature = "with_digest")]
+    pub fn insert_digest_with_prefix<D: Digest>(
+        &mut self,
+        prefix: impl AsRef<[u8]>,
+        element: impl AsRef<[u8]>,
+    ) {
+        self.insert(Element::from_digest_with_prefix::<D>(prefix, element))
+    }
+
+    /// Insert a new set member, reporting whether the sketch upgraded
+    ///
+    /// Returns the new representation's kind if this insert moved the sketch
+    /// to its next representation (see `distinct_until_upgrade`), and `None`
+    /// otherwise. Useful to track memory use without polling after every
+    /// insert.
+    pub fn insert_returning_upgrade(
+        &mut self,
+        element: Element<P, W>,
+    ) -> Option<RepresentationKind> {
+        let upgraded = self.insert_encoded_hash(element.0)?;
+        *self = upgraded;
+        Some(self.representation_kind())
+    }
+
+    /// Insert a new set member, unless it requires more than `max_bytes` of memory
+    ///
+    /// Refuses to leave the Array representation, or to grow the Sparse one,
+    /// when the sketch would then take more than `max_bytes`: `hll_size_of()`
+    /// for an upgrade to Hll, or the sketch and its sorted hashes for Sparse.
+    /// The element is not inserted and the sketch stays unchanged, so its
+    /// estimate stops growing. Smaller upgrades (Small to Array) are always
+    /// allowed.
+    pub fn try_insert(
+        &mut self,
+        element: Element<P, W>,
+        max_bytes: usize,
+    ) -> Result<(), CapacityError> {
+        let sparse_size_of = |len: usize| size_of::<Self>() + len * size_of::<u32>();
+        let required = match self {
+            Sketch::Array(arr) if arr.len() == ARRAY_MAX_CAPACITY && !arr.contains(&element.0) => {
+                if Sparse::<P, W>::ENABLED {
+                    sparse_size_of(ARRAY_MAX_CAPACITY + 1)
+                } else {
+                    Self::hll_size_of()
+                }
+            }
+            Sketch::Sparse(sparse) if !sparse.contains(element.0) => {
+                if sparse.len() == Sparse::<P, W>::MAX_LEN {
+                    Self::hll_size_of()
+                } else {
+                    sparse_size_of(sparse.len() + 1)
+                }
+            }
+            _ => 0,
+        };
+        if required > max_bytes {
+            return Err(CapacityError {
+                required,
+                budget: max_bytes,
+            });
+        }
+        self.insert(element);
+        Ok(())
+    }
+
+    /// Insert many set members to count
+    ///
+    /// Equivalent to calling `insert` for each element, but once the sketch
+    /// reaches the Hll representation, remaining elements are inserted
+    /// directly without re-checking the representation.
+    pub fn insert_all(&mut self, elements: impl IntoIterator<Item = Element<P, W>>) {
+        let mut elements = elements.into_iter();
+        while !matches!(self, Sketch::Hll(_)) {
+            let Some(element) = elements.next() else {
+                return;
+            };
+            self.insert(element);
+        }
+        if let Sketch::Hll(hll) = self {
+            for element in elements {
+                hll.insert_encoded_hash(element.0);
+            }
+        }
+    }
+
+    /// Insert already-hashed set members
+    ///
+    /// For ingesting 64-bit hashes from an external pipeline, like a database
+    /// that hashes with its own function. Equivalent to inserting
+    /// `Element::from_hashed` for each hash, with the bulk path of
+    /// `insert_all`. The hashes must be well distributed, see `from_hashed`.
+    pub fn insert_hashes(&mut self, hashes: impl IntoIterator<Item = u64>) {
+        self.insert_all(hashes.into_iter().map(Element::from_hashed));
+    }
+
+    /// Insert already-hashed set members packed as little-endian `u64`s
+    ///
+    /// Equivalent to inserting `Element::from_hashed` for each 8-byte record,
+    /// without parsing the buffer into another allocation first, e.g. straight
+    /// from a memory-mapped file. Fails without inserting anything if the
+    /// length isn't a multiple of 8.
+    pub fn insert_hashes_le(&mut self, bytes: &[u8]) -> Result<(), HashBytesError> {
+        if bytes.len() % 8 != 0 {
+            return Err(HashBytesError { len: bytes.len() });
+        }
+        self.insert_all(bytes.chunks_exact(8).map(|record| {
+            Element::from_hashed(u64::from_le_bytes(
+                record.try_into().expect("chunks are 8 bytes"),
+            ))
+        }));
+        Ok(())
+    }
+
+    /// Create a sketch from already-hashed set members
+    ///
+    /// Equivalent to inserting `Element::from_hashed` for each hash.
+    pub fn from_hashes(hashes: impl IntoIterator<Item = u64>) -> Self {
+        hashes.into_iter().map(Element::from_hashed).collect()
+    }
+
+    /// Create a sketch from hashes already encoded for this sketch's `P` and `W`
+    ///
+    /// For advanced use cases, like re-inserting the encoded hashes stored in
+    /// Small, Array and Sparse representations. The encoding is specific to `P` and
+    /// `W`: encoded hashes from sketches with other parameters will silently
+    /// produce wrong estimates. Values with a zero rank are not valid encoded
+    /// hashes, and are ignored.
+    pub fn from_encoded_hashes(encoded: impl IntoIterator<Item = u32>) -> Self {
+        let mut sketch = Sketch::default();
+        for h in encoded {
+            if h & ((1 << W) - 1) != 0 {
+                sketch.insert_encoded(h);
+            }
+        }
+        sketch
+    }
+
+    /// Check whether `element` may have been inserted into this sketch
+    ///
+    /// For Small, Array and Sparse representations this is exact, up to hash
+    /// collisions. HyperLogLog can't answer membership, so for Hll this only
+    /// checks that inserting `element` wouldn't change the sketch: `false`
+    /// means definitely absent, but `true` is a weak "possibly present" that
+    /// becomes a false positive for most absent elements as the sketch fills
+    /// up.
+    pub fn probably_contains(&self, element: Element<P, W>) -> bool {
+        match self {
+            Sketch::Small(small) => small.items().contains(&element.0),
+            Sketch::Array(arr) => arr.contains(&element.0),
+            Sketch::Sparse(sparse) => sparse.contains(element.0),
+            Sketch::Hll(hll) => hll.covers_encoded_hash(element.0),
+        }
+    }
+
     /// Compute the current estimated cardinality
     ///
     /// This is a fast operation:
-    /// - Small and Array representations are extremely cheap to compute
+    /// - Small, Array and Sparse representations are extremely cheap to compute
     /// - Hll updates computed state on insert, eliminating most of the work
+    ///
+    /// To order sketches by cardinality, sort them with
+    /// `sort_by_key(Sketch::estimate)`.
     pub fn estimate(&self) -> usize {
         self.estimate_sketch()
     }
 
+    /// Compute the estimated cardinality without rounding it
+    ///
+    /// For aggregating many estimates, like averaging them, without
+    /// accumulating rounding errors. Small, Array and Sparse representations
+    /// return their exact count. `estimate` is this value rounded to the nearest
+    /// integer.
+    pub fn estimate_f64(&self) -> f64 {
+        self.estimate_sketch_f64()
+    }
+
+    /// Compute the estimated cardinality with the half-width of its 95%
+    /// confidence interval
+    ///
+    /// Small, Array and Sparse representations are exact (up to hash
+    /// collisions), so their error bound is zero. For Hll, the bound is `1.96`
+    /// standard errors, where the relative standard error is `1.04 / sqrt(2^P)`.
+    /// This assumes well-distributed hashes and an approximately normal
+    /// estimate, which holds well once the sketch has many more items than
+    /// registers.
+    pub fn estimate_with_error_bound(&self) -> (usize, f64) {
+        let estimate = self.estimate();
+        let bound = match self {
+            Sketch::Small(_) | Sketch::Array(_) | Sketch::Sparse(_) => 0.0,
+            Sketch::Hll(_) => {
+                1.96 * HyperLogLog::<P, W>::relative_standard_error() * estimate as f64
+            }
+        };
+        (estimate, bound)
+    }
+
+    /// Compute the estimated cardinality with how far it can be trusted
+    ///
+    /// For automated decisions that should hold off on untrustworthy
+    /// estimates: Small, Array and Sparse representations are `Exact`, and Hll
+    /// estimates are `Degraded` above `max_reliable_cardinality`, where
+    /// registers saturate, and `Normal` otherwise.
+    pub fn estimate_checked(&self) -> (usize, Confidence) {
+        let confidence = match self {
+            Sketch::Small(_) | Sketch::Array(_) | Sketch::Sparse(_) => Confidence::Exact,
+            Sketch::Hll(_) if self.estimate_f64() > HyperLogLog::<P, W>::SATURATION_THRESHOLD => {
+                Confidence::Degraded
+            }
+            Sketch::Hll(_) => Confidence::Normal,
+        };
+        (self.estimate(), confidence)
+    }
+
+    /// Compute the `(lower, point, upper)` bounds of the estimated cardinality
+    /// for a z-score, e.g. `1.96` for a 95% confidence interval
+    ///
+    /// Small, Array and Sparse representations return their exact count three
+    /// times.
+    /// For Hll, the bounds are `z` standard errors away from the estimate,
+    /// rounded outwards, with the lower bound clamped at zero.
+    pub fn estimate_range(&self, z: f64) -> (usize, usize, usize) {
+        let estimate = self.estimate();
+        match self {
+            Sketch::Small(_) | Sketch::Array(_) | Sketch::Sparse(_) => {
+                (estimate, estimate, estimate)
+            }
+            Sketch::Hll(_) => {
+                let bound = z * HyperLogLog::<P, W>::relative_standard_error() * estimate as f64;
+                let lower = libm::floor(estimate as f64 - bound).max(0.0) as usize;
+                let upper = libm::ceil(estimate as f64 + bound) as usize;
+                (lower, estimate, upper)
+            }
+        }
+    }
+
+    /// The `P` (precision) parameter of this sketch
+    ///
+    /// ```
+    /// use cardinality_estimator_safe::Sketch;
+    ///
+    /// let sketch = Sketch::<14, 5>::default();
+    /// assert_eq!(sketch.precision(), 14);
+    /// assert_eq!(sketch.width(), 5);
+    /// ```
+    pub const fn precision(&self) -> usize {
+        P
+    }
+
+    /// The `W` (register width) parameter of this sketch
+    pub const fn width(&self) -> usize {
+        W
+    }
+
+    /// Relative standard error of estimates once the sketch uses the Hll
+    /// representation: `1.04 / sqrt(2^P)`
+    ///
+    /// Depends only on `P`, so it can guide the choice of parameters before
+    /// creating any sketch. For example, about `0.0163` for `P = 12`.
+    pub fn standard_error() -> f64 {
+        HyperLogLog::<P, W>::relative_standard_error()
+    }
+
+    /// Approximate cardinality above which registers start to saturate
+    ///
+    /// Registers can hold ranks up to `2^W - 1` (or the hash bits left after
+    /// `P`). Above this cardinality at least 1% of registers are expected to
+    /// hold that maximum rank: estimates switch to a slower maximum-likelihood
+    /// method and lose accuracy as more registers saturate. Pick `W` so your
+    /// expected cardinalities stay below it.
+    pub fn max_reliable_cardinality() -> u64 {
+        HyperLogLog::<P, W>::SATURATION_THRESHOLD as u64
+    }
+
+    /// Memory footprint in bytes of a sketch using the Hll representation,
+    /// including its heap-allocated registers and register histogram
+    ///
+    /// This is the memory of the Hll representation only: at small `P`, a
+    /// full Array or Sparse sketch can take more (about 536 bytes for a full
+    /// Array, against 124 for the Hll at `P = 4, W = 4`).
+    ///
+    /// With the `theta` feature, this includes a full sample of smallest
+    /// hashes (see `theta_intersection`), and with the `minhash` feature, the
+    /// MinHash bins (see `minhash_signature`).
+    pub const fn hll_size_of() -> usize {
+        #[cfg(feature = "theta")]
+        let theta = THETA_K * size_of::<u32>();
+        #[cfg(not(feature = "theta"))]
+        let theta = 0;
+        #[cfg(feature = "minhash")]
+        let minhash = MINHASH_K * size_of::<u32>();
+        #[cfg(not(feature = "minhash"))]
+        let minhash = 0;
+        let histogram = (1 << W) * size_of::<u32>();
+        size_of::<Self>() + Self::dense_hll_bytes() + histogram + theta + minhash
+    }
+
+    /// Size in bytes of the heap allocation holding the registers of the Hll
+    /// representation
+    ///
+    /// Usable in const contexts, e.g. to size arenas for dense sketches:
+    ///
+    /// ```
+    /// use cardinality_estimator_safe::Sketch;
+    ///
+    /// const DENSE_BYTES: usize = Sketch::<12, 6>::dense_hll_bytes();
+    /// let arena = [0u8; DENSE_BYTES * 4];
+    /// assert_eq!(arena.len(), 4 * 3084);
+    /// ```
+    pub const fn dense_hll_bytes() -> usize {
+        HyperLogLog::<P, W>::HLL_SLICE_LEN * size_of::<u32>()
+    }
+
+    /// Number of distinct elements inserted, like `HashSet::len`
+    ///
+    /// Same as `estimate()`: exact for the Small, Array and Sparse
+    /// representations (up to collisions of the encoded hashes, see
+    /// `is_exact`), but only an estimate for Hll.
+    ///
+    /// ```
+    /// use cardinality_estimator_safe::{Element, Sketch};
+    /// use wyhash::WyHash;
+    ///
+    /// let mut sketch: Sketch = Sketch::default();
+    /// assert_eq!(sketch.len(), 0);
+    /// sketch.insert(Element::from_hasher_default::<WyHash>("a"));
+    /// sketch.insert(Element::from_hasher_default::<WyHash>("b"));
+    /// sketch.insert(Element::from_hasher_default::<WyHash>("a"));
+    /// assert_eq!(sketch.len(), 2);
+    /// ```
+    pub fn len(&self) -> usize {
+        self.estimate()
+    }
+
+    /// Decoded rank of each of the `2^P` HyperLogLog registers, in index order
+    ///
+    /// Returns `None` for the Small, Array and Sparse representations, which
+    /// store encoded hashes rather than registers.
+    pub fn hll_registers(&self) -> Option<Vec<u8>> {
+        match self {
+            Sketch::Hll(hll) => Some(hll.decoded_registers()),
+            Sketch::Small(_) | Sketch::Array(_) | Sketch::Sparse(_) => None,
+        }
+    }
+
+    /// Replace this sketch with HyperLogLog registers from an external source
+    ///
+    /// For warm-starting from a cache. `registers` has the layout of the
+    /// serialized Hll representation: `2^P * W / 32` words of `W`-bit
+    /// registers packed from the least significant bit, then 3 words that are
+    /// ignored. The number of zero registers and the harmonic sum are
+    /// recomputed. Fails without modifying this sketch if the length is wrong
+    /// or a register holds a rank no hash can produce.
+    pub fn load_hll_registers(&mut self, registers: &[u32]) -> Result<(), LoadError> {
+        let expected = HyperLogLog::<P, W>::HLL_SLICE_LEN;
+        if registers.len() != expected {
+            return Err(LoadError::InvalidLength {
+                expected,
+                found: registers.len(),
+            });
+        }
+        let hll = HyperLogLog::<P, W>::from_registers(registers.to_vec());
+        let invalid = (hll.decoded_registers().into_iter().enumerate())
+            .find(|&(_, rank)| usize::from(rank) > HyperLogLog::<P, W>::MAX_RANK);
+        if let Some((index, rank)) = invalid {
+            return Err(LoadError::InvalidRank { index, rank });
+        }
+        *self = Sketch::Hll(hll);
+        Ok(())
+    }
+
+    /// Build a sketch from the 64-bit word of a Small representation
+    ///
+    /// For reconstructing sketches of up to two elements from a custom
+    /// storage format. `data` has the layout of the serialized Small
+    /// representation: type bits `00`, then two 31-bit encoded hashes, with
+    /// zero marking an empty slot. Fails if the type bits are set, the 2-nd
+    /// slot is filled while the 1-st is empty, both slots hold the same hash,
+    /// or a hash has a zero rank.
+    pub fn from_small_word(data: u64) -> Result<Self, ParseError> {
+        let small = Small::from(data);
+        if !small.is_well_formed() {
+            return Err(ParseError::InvalidSmallWord(data));
+        }
+        Ok(Sketch::Small(small))
+    }
+
+    /// Iterate the decoded rank of each HyperLogLog register, in index order
+    ///
+    /// Like `hll_registers`, without collecting them: yields `2^P` ranks for
+    /// the Hll representation, and nothing for the others.
+    pub fn registers(&self) -> impl Iterator<Item = u8> + '_ {
+        let hll = match self {
+            Sketch::Hll(hll) => Some(hll),
+            Sketch::Small(_) | Sketch::Array(_) | Sketch::Sparse(_) => None,
+        };
+        hll.into_iter().flat_map(HyperLogLog::iter_registers)
+    }
+
+    /// Number of HyperLogLog registers at each rank
+    ///
+    /// Index `r` of the `2^W` counts holds the number of registers with rank
+    /// `r`, so they sum to `2^P`. This is the input to maximum-likelihood
+    /// estimation (see `estimate_mle`), and can reveal badly distributed
+    /// hashes. Returns `None` for the Small, Array and Sparse representations.
+    pub fn register_histogram(&self) -> Option<Vec<u32>> {
+        match self {
+            Sketch::Hll(hll) => Some(hll.histogram.to_vec()),
+            Sketch::Small(_) | Sketch::Array(_) | Sketch::Sparse(_) => None,
+        }
+    }
+
+    /// Compute the estimated cardinality with a maximum-likelihood estimator
+    ///
+    /// An alternative to the LogLog-Beta estimator used by `estimate`, which
+    /// can be more accurate for Hll sketches near saturation. It is more
+    /// expensive to compute, since it iterates to solve for the estimate over
+    /// the register histogram. Small, Array and
+    /// Sparse representations return their exact count, same as `estimate`.
+    pub fn estimate_mle(&self) -> usize {
+        match self {
+            Sketch::Hll(hll) => hll.estimate_mle(),
+            Sketch::Small(_) | Sketch::Array(_) | Sketch::Sparse(_) => self.estimate(),
+        }
+    }
+
+    /// Check if nothing has been inserted into this sketch
+    ///
+    /// Cheaper than comparing `estimate()` to zero for the Hll representation.
+    pub fn is_empty(&self) -> bool {
+        self.is_empty_sketch()
+    }
+
+    /// Check if this sketch still counts exactly, in the Small, Array or
+    /// Sparse representation
+    ///
+    /// While this is true, the sketch holds the encoded hash of every distinct
+    /// element, so it can serve as a precise set: `estimate()` is exactly the
+    /// number of distinct elements inserted, and `probably_contains` has no
+    /// false positives, both up to collisions of the encoded hashes (about
+    /// `n^2 / 2^(32 - W)` chance for `n` elements, so at most 1.6% for the
+    /// 1024 elements held at `W = 6`). It flips to false for
+    /// good once the sketch is promoted to the approximate Hll representation.
+    pub fn is_exact(&self) -> bool {
+        !matches!(self, Sketch::Hll(_))
+    }
+
+    /// Check if this sketch and `other` estimate the same cardinality, within
+    /// `relative_tolerance` of the larger estimate
+    ///
+    /// Unlike `==`, which compares the stored hashes and registers exactly,
+    /// this only looks at `estimate_f64`, so sketches of the same items built
+    /// with different hash seeds or parameters can match. Two empty sketches
+    /// are always equivalent.
+    pub fn approx_eq(&self, other: &Self, relative_tolerance: f64) -> bool {
+        let (lhs, rhs) = (self.estimate_f64(), other.estimate_f64());
+        (lhs - rhs).abs() <= relative_tolerance * lhs.max(rhs)
+    }
+
+    /// Reset this sketch to empty, as if it were newly created
+    ///
+    /// The sketch returns to the `Small` representation, which has no heap
+    /// storage: any allocation held by an `Array`, `Sparse` or `Hll`
+    /// representation is released, not retained for reuse.
+    pub fn clear(&mut self) {
+        *self = Sketch::default();
+    }
+
+    /// Upgrade this sketch to the Hll representation now, keeping its elements
+    ///
+    /// Avoids the incremental Small, Array, Sparse and Hll allocations when many
+    /// elements are about to be inserted: once dense, inserts never allocate.
+    /// The estimate is unchanged: the Hll sketch keeps the exact count of the
+    /// elements it was promoted with as its estimate until the next insert or
+    /// merge, and never estimates below it afterwards.
+    pub fn reserve_dense(&mut self) {
+        self.hll_mut();
+    }
+
+    /// Release the registers of an Hll sketch that holds nothing
+    ///
+    /// HyperLogLog registers can't be turned back into the exact hashes that
+    /// the other representations store, so this is best-effort: only
+    /// an empty Hll sketch, which can come from merging empty Hll sketches, is
+    /// returned to the Small representation. Any other sketch is unchanged.
+    pub fn compact(&mut self) {
+        if matches!(self, Sketch::Hll(_)) && self.is_empty() {
+            self.clear();
+        }
+    }
+
+    /// Release memory allocated beyond what the current representation uses
+    ///
+    /// The Array and Sparse representations over-allocate as they grow, and
+    /// Array pads its items with zeros up to a power of two. Shrinking them to
+    /// exactly their items saves memory when holding many sketches that won't
+    /// grow much further. Inserts still work afterwards, reallocating as
+    /// needed.
+    pub fn shrink_to_fit(&mut self) {
+        match self {
+            Sketch::Small(_) => {}
+            Sketch::Array(array) => array.shrink_to_fit(),
+            Sketch::Sparse(sparse) => sparse.shrink_to_fit(),
+            Sketch::Hll(hll) => hll.registers.shrink_to_fit(),
+        }
+    }
+
+    /// Recount the register histogram cached by the Hll representation from
+    /// its registers
+    ///
+    /// Inserts and merges keep the histogram exact, so this is only needed
+    /// when the cached values may be wrong. The other representations cache
+    /// nothing, so this is a no-op for them.
+    pub fn recompute_cached(&mut self) {
+        if let Sketch::Hll(hll) = self {
+            hll.refresh_stats();
+        }
+    }
+
+    #[inline]
+    /// Memory used by the current representation in bytes, including its heap
+    /// allocation
+    ///
+    /// Counts the allocated capacity of the Array, Sparse and Hll storage, not just
+    /// the `Vec` header, so it's suitable for capacity planning. Doesn't
+    /// include the `Sketch` enum's own padding around the representation.
+    pub fn heap_size(&self) -> usize {
+        SketchTrait::heap_size(self)
+    }
+
+    /// Iterate the encoded hashes stored by the Small, Array and Sparse
+    /// representations
+    ///
+    /// Returns `None` for Hll, which doesn't keep the hashes. The encoded
+    /// hashes can be inserted into another sketch with the same `P` and `W`
+    /// with `from_encoded_hashes`, e.g. for exact set operations before
+    /// promotion.
+    pub fn iter_sparse(&self) -> Option<impl Iterator<Item = u32> + '_> {
+        let (small, array): ([u32; 2], &[u32]) = match self {
+            Sketch::Small(small) => (small.items(), &[]),
+            Sketch::Array(arr) => ([0, 0], arr),
+            Sketch::Sparse(sparse) => ([0, 0], sparse),
+            Sketch::Hll(_) => return None,
+        };
+        Some(
+            small
+                .into_iter()
+                .filter(|&h| h != 0)
+                .chain(array.iter().copied()),
+        )
+    }
+
+    /// Number of additional distinct elements whose insert would upgrade this
+    /// sketch to its next representation
+    ///
+    /// The 3rd distinct element upgrades Small to Array, the 129th upgrades
+    /// Array to Sparse (or to Hll for `P` below 10), and Sparse upgrades to
+    /// Hll once its hashes would outgrow the `2^P * W / 32 + 3` words of the
+    /// registers or 1024 elements, so this is `3 - n`, `129 - n` or
+    /// `min(2^P * W / 32 + 3, 1024) + 1 - n` for `n` distinct elements. Returns `None` for Hll, which never upgrades.
+    /// Useful to group inserts so that an upgrade's reallocation happens once
+    /// per batch.
+    pub fn distinct_until_upgrade(&self) -> Option<usize> {
+        match self {
+            Sketch::Small(_) => Some(3 - self.estimate()),
+            Sketch::Array(arr) => Some(ARRAY_MAX_CAPACITY + 1 - arr.len()),
+            Sketch::Sparse(sparse) => Some(Sparse::<P, W>::MAX_LEN + 1 - sparse.len()),
+            Sketch::Hll(_) => None,
+        }
+    }
+
+    /// Kind of representation this sketch is currently using
+    ///
+    /// Purely observational, e.g. for metrics on how many sketches are still
+    /// in the cheap Small representation.
+    pub fn representation_kind(&self) -> RepresentationKind {
+        match self {
+            Sketch::Small(_) => RepresentationKind::Small,
+            Sketch::Array(_) => RepresentationKind::Array,
+            Sketch::Sparse(_) => RepresentationKind::Sparse,
+            Sketch::Hll(_) => RepresentationKind::Hll,
+        }
+    }
+
     #[inline]
     fn insert_encoded(&mut self, encoded: u32) {
         if let Some(upgraded) = self.insert_encoded_hash(encoded) {
@@ -68,6 +811,14 @@ impl<const P: usize, const W: usize> Sketch<P, W> {
     ///
     /// Equivalent to inserting all elements from `rhs` into this sketch, but
     /// much cheaper.
+    ///
+    /// Merging is commutative and associative: merging the same sketches in
+    /// any order or grouping gives equal sketches with bit-identical
+    /// estimates, so distributed aggregations are reproducible whatever
+    /// order shards arrive in: the Hll representation accumulates its
+    /// harmonic sum exactly, so it only depends on the registers. Merging Hll
+    /// sketches keeps the smaller of the element counts they were promoted
+    /// with, which their estimates never go below.
     #[inline]
     pub fn merge(&mut self, rhs: &Self) {
         match &rhs {
@@ -83,6 +834,11 @@ impl<const P: usize, const W: usize> Sketch<P, W> {
                     self.insert_encoded(h);
                 }
             }
+            Sketch::Sparse(rhs_sparse) => {
+                for &h in rhs_sparse.deref() {
+                    self.insert_encoded(h);
+                }
+            }
             Sketch::Hll(rhs_hll) => {
                 match self {
                     Sketch::Small(lhs_small) => {
@@ -104,6 +860,15 @@ impl<const P: usize, const W: usize> Sketch<P, W> {
                         }
                         *self = Sketch::Hll(hll);
                     }
+                    Sketch::Sparse(lhs_sparse) => {
+                        let mut hll = rhs_hll.clone();
+                        for &h in &**lhs_sparse {
+                            if hll.insert_encoded_hash(h).is_some() {
+                                panic!("inserting into hll rep must yield hll rep");
+                            };
+                        }
+                        *self = Sketch::Hll(hll);
+                    }
                     Sketch::Hll(lhs_hll) => {
                         lhs_hll.merge(rhs_hll);
                     }
@@ -111,74 +876,573 @@ impl<const P: usize, const W: usize> Sketch<P, W> {
             }
         }
     }
-}
 
-impl<const P: usize, const W: usize> Default for Sketch<P, W> {
-    /// Initialize an empty sketch with cardinality=0
-    fn default() -> Self {
-        Sketch::Small(Default::default())
+    /// Merge another sketch into this one by taking the register-wise maximum
+    /// of their ranks
+    ///
+    /// This is the standard HyperLogLog union, exposed as a building block for
+    /// custom multi-sketch operations. Unlike `merge`, it ignores the exact
+    /// Small, Array and Sparse representations: both sketches are treated as
+    /// HyperLogLog registers, and this sketch always ends up in the Hll
+    /// representation, with each register holding the higher rank of the two.
+    /// When both sketches are already Hll, this is the same as `merge`.
+    pub fn merge_max_rank_only(&mut self, rhs: &Self) {
+        let lhs = self.hll_mut();
+        match rhs {
+            Sketch::Hll(rhs) => lhs.merge(rhs),
+            _ => {
+                let items: Vec<u32> = rhs.iter_sparse().into_iter().flatten().collect();
+                lhs.merge(&HyperLogLog::new(&items));
+            }
+        }
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use test_case::test_case;
-    use wyhash::WyHash;
-
-    #[cfg(feature = "with_digest")]
-    #[test]
-    fn test_estimator_with_prefix() {
-        use sha2::Sha256;
 
-        let mut estimator1: Sketch = Sketch::default();
-        assert_eq!(estimator1.estimate(), 0);
-        estimator1.insert(Element::from_digest_with_prefix::<Sha256>(
-            "secret", "hello",
-        ));
-        assert_eq!(estimator1.estimate(), 1);
-
-        let mut estimator2: Sketch = Sketch::default();
-        estimator2.insert(Element::from_digest_with_prefix::<Sha256>(
-            "secret", "hello",
-        ));
-        estimator1.merge(&estimator2);
-        assert_eq!(estimator1.estimate(), 1);
-
-        let mut estimator3: Sketch = Sketch::default();
-        estimator3.insert(Element::from_digest_with_prefix::<Sha256>("sauce", "hello"));
-        estimator1.merge(&estimator3);
-        assert_eq!(estimator1.estimate(), 2);
+    /// Merge another sketch into this one, and return the relative error of the
+    /// merged estimate against the true size of the union
+    ///
+    /// For accuracy test suites, where `true_union` is computed from the raw
+    /// items. The error is `|estimate - true_union| / true_union`: zero for an
+    /// exact estimate, and infinite if the union is empty but the estimate is
+    /// not.
+    pub fn merge_and_compare(&mut self, rhs: &Self, true_union: usize) -> f64 {
+        self.merge(rhs);
+        let error = self.estimate().abs_diff(true_union);
+        if error == 0 {
+            0.0
+        } else {
+            error as f64 / true_union as f64
+        }
     }
 
-    #[test]
-    fn small_size() {
-        assert_eq!(Sketch::<0, 0>::default().size_of(), 8);
+    /// Merge another sketch into this one, reporting the resulting representation
+    ///
+    /// Merging a large sketch into a small one can jump straight from Small to
+    /// Hll, so the returned kind lets callers update memory accounting after
+    /// batch merges.
+    pub fn merge_reporting(&mut self, rhs: &Self) -> RepresentationKind {
+        self.merge(rhs);
+        self.representation_kind()
     }
 
-    #[test_case(0 => "representation: Small(estimate: 0), avg_err: 0.0000")]
-    #[test_case(1 => "representation: Small(estimate: 1), avg_err: 0.0000")]
-    #[test_case(2 => "representation: Small(estimate: 2), avg_err: 0.0000")]
-    #[test_case(3 => "representation: Array(estimate: 3), avg_err: 0.0000")]
-    #[test_case(4 => "representation: Array(estimate: 4), avg_err: 0.0000")]
-    #[test_case(8 => "representation: Array(estimate: 8), avg_err: 0.0000")]
-    #[test_case(16 => "representation: Array(estimate: 16), avg_err: 0.0000")]
-    #[test_case(17 => "representation: Array(estimate: 17), avg_err: 0.0000")]
+    /// Merge a sketch with the same `P` and a narrower or equal `W` into this one
+    ///
+    /// Useful to combine legacy sketches stored with a smaller register width.
+    /// Register indices only depend on `P`, so ranks carry over unchanged.
+    ///
+    /// Accuracy: ranks from `rhs` were capped at `2^W2 - 1`, so registers that
+    /// saturated in `rhs` stay at that lower cap instead of the higher rank
+    /// they would have reached with width `W`. The merged estimate is as
+    /// accurate as a `W` sketch when `rhs` had few saturated registers, but
+    /// inherits `rhs`'s underestimate at very high cardinalities.
+    ///
+    /// Merging a wider sketch is a compile-time error.
+    pub fn merge_widening<const W2: usize>(&mut self, rhs: &Sketch<P, W2>) {
+        const { assert!(W2 <= W, "`rhs` must not have wider registers than `self`") }
+        match rhs {
+            Sketch::Small(rhs_small) => {
+                for h in rhs_small.items() {
+                    if h != 0 {
+                        self.insert_encoded(widen_encoded::<W2, W>(h));
+                    }
+                }
+            }
+            Sketch::Array(rhs_arr) => {
+                for &h in rhs_arr.deref() {
+                    self.insert_encoded(widen_encoded::<W2, W>(h));
+                }
+            }
+            Sketch::Sparse(rhs_sparse) => {
+                for &h in rhs_sparse.deref() {
+                    self.insert_encoded(widen_encoded::<W2, W>(h));
+                }
+            }
+            Sketch::Hll(rhs_hll) => self.hll_mut().merge_narrower(rhs_hll),
+        }
+    }
+
+    /// Merge a `CompactSketch` serialized with `postcard` into this sketch
+    ///
+    /// Takes the bytes of `postcard::to_allocvec(&CompactSketch(sketch))`. The
+    /// hashes of the Small, Array and Sparse representations are inserted, and
+    /// the packed registers of the HyperLogLog representation are folded into
+    /// this sketch directly from `bytes`, without unpacking an intermediate
+    /// sketch, which makes rollups over many stored sketches cheaper. The
+    /// element count stored before them is merged like `merge` does (see
+    /// `promoted_count`).
+    ///
+    /// Returns `ParseError::InvalidSketch` if `bytes` isn't exactly a
+    /// serialized `CompactSketch` with these parameters. This sketch is left
+    /// unchanged then.
+    #[cfg(feature = "merge_from_serialized")]
+    pub fn merge_from_serialized(&mut self, bytes: &[u8]) -> Result<(), ParseError> {
+        let (compact, rest) = postcard::take_from_bytes::<CompactBorrowed<P, W>>(bytes)
+            .map_err(|_| ParseError::InvalidSketch)?;
+        if !rest.is_empty() {
+            return Err(ParseError::InvalidSketch);
+        }
+        match compact {
+            CompactBorrowed::Small(small) => self.merge(&Sketch::Small(small)),
+            CompactBorrowed::Array(arr) => self.merge(&Sketch::Array(arr)),
+            CompactBorrowed::Sparse(sparse) => self.merge(&Sketch::Sparse(sparse)),
+            CompactBorrowed::Hll(PackedHllBorrowed { count, registers }) => {
+                self.hll_mut().merge_ranks(registers.ranks(), count);
+            }
+        }
+        Ok(())
+    }
+
+    /// Move into a sketch with the same parameters given as other const generics
+    ///
+    /// Lets generic code hand a `Sketch<P, W>` to code matching on concrete
+    /// parameters.
+    ///
+    /// Caller is responsible for ensuring `(P2, W2) == (P, W)`
+    pub(crate) fn cast<const P2: usize, const W2: usize>(self) -> Sketch<P2, W2> {
+        debug_assert_eq!((P, W), (P2, W2), "sketch parameters must match to cast");
+        match self {
+            Sketch::Small(small) => Sketch::Small(small.cast()),
+            Sketch::Array(arr) => Sketch::Array(arr.cast()),
+            Sketch::Sparse(sparse) => Sketch::Sparse(sparse.cast()),
+            Sketch::Hll(hll) => Sketch::Hll(hll.cast()),
+        }
+    }
+
+    /// Upgrade this sketch to the Hll representation if needed, and return it
+    fn hll_mut(&mut self) -> &mut HyperLogLog<P, W> {
+        let items: Vec<u32> = match self {
+            Sketch::Small(small) => small.items().into_iter().filter(|&h| h != 0).collect(),
+            Sketch::Array(arr) => arr.to_vec(),
+            Sketch::Sparse(sparse) => sparse.to_vec(),
+            Sketch::Hll(_) => Vec::new(),
+        };
+        if !matches!(self, Sketch::Hll(_)) {
+            *self = Sketch::Hll(HyperLogLog::new(&items));
+        }
+        let Sketch::Hll(hll) = self else {
+            unreachable!("sketch was just upgraded to hll");
+        };
+        hll
+    }
+
+    /// Merge many other sketches into this one
+    ///
+    /// Equivalent to calling `merge` for each of `others`, but all Hll
+    /// sketches are merged in a single pass over the registers.
+    pub fn merge_many(&mut self, others: &[Self]) {
+        let mut hlls = others.iter().filter_map(|other| match other {
+            Sketch::Hll(hll) => Some(hll),
+            _ => None,
+        });
+        if let Some(first) = hlls.next() {
+            match self {
+                Sketch::Hll(lhs_hll) => lhs_hll.merge(first),
+                _ => {
+                    let mut union = Sketch::Hll(first.clone());
+                    union.merge(self);
+                    *self = union;
+                }
+            }
+            let rest: Vec<_> = hlls.collect();
+            if let Sketch::Hll(lhs_hll) = self {
+                lhs_hll.merge_many(&rest);
+            }
+        }
+        for other in others {
+            if !matches!(other, Sketch::Hll(_)) {
+                self.merge(other);
+            }
+        }
+    }
+
+    /// Merge the sketches yielded by an iterator of references into this one
+    ///
+    /// Equivalent to calling `merge` for each of `others`, but composes with
+    /// iterator adapters like `map.values().filter(..)` without collecting
+    /// the references first. To merge a slice, `merge_many` is faster.
+    pub fn merge_from<'a, I>(&mut self, others: I)
+    where
+        I: IntoIterator<Item = &'a Self>,
+        Self: 'a,
+    {
+        for other in others {
+            self.merge(other);
+        }
+    }
+
+    /// Merge many sketches into a new one
+    ///
+    /// Starts from a clone of the largest representation, so the result is
+    /// upgraded at most once, then merges the rest with `merge_many`. The
+    /// result is equal to folding `merge` over `sketches` from an empty
+    /// sketch, though Array and Sparse items may be stored in a different order.
+    pub fn merge_all(sketches: &[Self]) -> Self {
+        let largest = sketches
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, sketch)| match sketch {
+                Sketch::Small(_) => 0,
+                Sketch::Array(arr) => arr.len(),
+                Sketch::Sparse(sparse) => sparse.len(),
+                Sketch::Hll(_) => usize::MAX,
+            })
+            .map(|(i, _)| i);
+        let Some(largest) = largest else {
+            return Sketch::default();
+        };
+        let mut merged = sketches[largest].clone();
+        merged.merge_many(&sketches[..largest]);
+        merged.merge_many(&sketches[largest + 1..]);
+        merged
+    }
+
+    /// Merge many sketches into a new one in parallel
+    ///
+    /// Merge is associative and commutative, so sketches are merged in
+    /// parallel chunks and the partial results combined in a tree reduction.
+    /// The result is equal to merging sequentially, with the same estimate.
+    #[cfg(feature = "rayon")]
+    pub fn par_merge_all(sketches: &[Self]) -> Self {
+        use rayon::prelude::*;
+
+        sketches
+            .par_iter()
+            .fold(Sketch::default, |mut acc, sketch| {
+                acc.merge(sketch);
+                acc
+            })
+            .reduce(Sketch::default, |mut lhs, rhs| {
+                lhs.merge(&rhs);
+                lhs
+            })
+    }
+
+    /// Create a new sketch representing the union of this one and `rhs`
+    ///
+    /// Equivalent to cloning this sketch and merging `rhs` into it, but
+    /// neither operand is modified, and only one clone is made.
+    pub fn union(&self, rhs: &Self) -> Self {
+        match (self, rhs) {
+            // merging into a sparse rep would discard it for a clone of `rhs`
+            (Sketch::Small(_) | Sketch::Array(_) | Sketch::Sparse(_), Sketch::Hll(_)) => {
+                let mut union = rhs.clone();
+                union.merge(self);
+                union
+            }
+            _ => {
+                let mut union = self.clone();
+                union.merge(rhs);
+                union
+            }
+        }
+    }
+
+    /// Estimate the number of elements present in both this sketch and `rhs`
+    ///
+    /// Uses inclusion-exclusion: `|A ∩ B| = |A| + |B| - |A ∪ B|`, clamped at
+    /// zero. The absolute error is on the order of the error of the union
+    /// estimate, so the *relative* error becomes very large when the
+    /// intersection is small compared to either set.
+    pub fn estimate_intersection(&self, rhs: &Self) -> usize {
+        (self.estimate() + rhs.estimate()).saturating_sub(self.union(rhs).estimate())
+    }
+
+    /// Estimate the number of elements present in both this sketch and `rhs`
+    /// from samples of their smallest hashes, like Theta sketches
+    ///
+    /// With the `theta` feature, the Hll representation keeps the 1024
+    /// smallest encoded hashes alongside its registers, while the other
+    /// representations hold all of theirs. Comparing the hashes below both
+    /// samples' largest one estimates the intersection with a relative error of roughly
+    /// `1 / sqrt(1024 * |A ∩ B| / |A ∪ B|)`, much lower than
+    /// `estimate_intersection` for small overlaps. Two sparse sketches are
+    /// intersected exactly.
+    ///
+    /// Returns `None` if either sketch has lost its sample: registers loaded
+    /// or merged from outside a sketch (deserialization, `load_hll_registers`,
+    /// `merge_from_serialized`, `merge_widening` of Hll sketches) don't carry
+    /// one. Samples are not compared by `==`.
+    #[cfg(feature = "theta")]
+    pub fn theta_intersection(&self, rhs: &Self) -> Option<usize> {
+        let (lhs, lhs_threshold) = self.theta_sample()?;
+        let (rhs, rhs_threshold) = rhs.theta_sample()?;
+        Some(theta::intersection(
+            (&lhs, lhs_threshold),
+            (&rhs, rhs_threshold),
+        ))
+    }
+
+    /// Sorted smallest encoded hashes, complete below the returned threshold
+    #[cfg(feature = "theta")]
+    fn theta_sample(&self) -> Option<(Cow<'_, [u32]>, u32)> {
+        match self {
+            Sketch::Hll(hll) => {
+                let kmv = hll.kmv.as_ref()?;
+                Some((Cow::Borrowed(kmv.hashes()), kmv.threshold()))
+            }
+            Sketch::Small(_) | Sketch::Array(_) | Sketch::Sparse(_) => {
+                let mut items: Vec<u32> = self.iter_sparse()?.collect();
+                items.sort_unstable();
+                Some((Cow::Owned(items), theta::COMPLETE))
+            }
+        }
+    }
+
+    /// MinHash signature of the elements inserted, for locality-sensitive
+    /// hashing
+    ///
+    /// With the `minhash` feature, the Hll representation keeps the smallest
+    /// scrambled encoded hash in each of `MINHASH_K` bins alongside its
+    /// registers, and the other representations compute them from the hashes
+    /// they hold. Bins without any hash are `u32::MAX`. The fraction of equal
+    /// positions in two signatures, ignoring bins empty in both, estimates the
+    /// Jaccard similarity of the sets (see `signature_similarity`), so
+    /// signatures can be split in bands and hashed to bucket similar sketches.
+    /// Signatures are only comparable between sketches with the same `P` and `W`.
+    ///
+    /// Returns `None` if the sketch has lost its bins, like for
+    /// `theta_intersection`: registers loaded or merged from outside a sketch
+    /// don't carry them.
+    #[cfg(feature = "minhash")]
+    pub fn minhash_signature(&self) -> Option<[u32; MINHASH_K]> {
+        match self {
+            Sketch::Hll(hll) => Some(hll.minhash.as_ref()?.signature()),
+            Sketch::Small(_) | Sketch::Array(_) | Sketch::Sparse(_) => {
+                Some(MinHash::from_hashes(self.iter_sparse()?).signature())
+            }
+        }
+    }
+
+    /// Estimate the number of elements present in this sketch but not in `rhs`
+    ///
+    /// Uses `|A \ B| = |A| - |A ∩ B|`, clamped at zero. This compounds the
+    /// error of the intersection estimate with the error of `|A|`, so, like
+    /// `estimate_intersection`, the relative error becomes very large when the
+    /// difference is small compared to either set.
+    pub fn estimate_difference(&self, rhs: &Self) -> usize {
+        self.estimate()
+            .saturating_sub(self.estimate_intersection(rhs))
+    }
+
+    /// Estimate the Jaccard similarity `|A ∩ B| / |A ∪ B|` of this sketch and `rhs`
+    ///
+    /// The result is in `[0, 1]`, and is `0.0` when both sketches are empty.
+    /// The intersection is estimated as in `estimate_intersection`, so the same
+    /// caveats apply to low similarities.
+    pub fn jaccard(&self, rhs: &Self) -> f64 {
+        let union = self.union(rhs).estimate();
+        if union == 0 {
+            return 0.0;
+        }
+        let intersection = (self.estimate() + rhs.estimate()).saturating_sub(union);
+        (intersection as f64 / union as f64).min(1.0)
+    }
+}
+
+impl<const P: usize, const W: usize> Default for Sketch<P, W> {
+    /// Initialize an empty sketch with cardinality=0
+    fn default() -> Self {
+        Sketch::Small(Default::default())
+    }
+}
+
+impl<const P: usize, const W: usize> fmt::Display for Sketch<P, W> {
+    /// Format just the estimated cardinality
+    ///
+    /// ```
+    /// use cardinality_estimator_safe::{Element, Sketch};
+    /// use wyhash::WyHash;
+    ///
+    /// let sketch: Sketch = (0..28).map(Element::from_hasher_default::<WyHash>).collect();
+    /// assert_eq!(format!("{}", sketch), "28");
+    /// assert_eq!(format!("{:?}", sketch), "Array(estimate: 28)");
+    /// ```
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.estimate(), f)
+    }
+}
+
+impl<const P: usize, const W: usize> Extend<Element<P, W>> for Sketch<P, W> {
+    fn extend<I: IntoIterator<Item = Element<P, W>>>(&mut self, iter: I) {
+        self.insert_all(iter);
+    }
+}
+
+impl<'a, const P: usize, const W: usize> Extend<&'a Element<P, W>> for Sketch<P, W> {
+    fn extend<I: IntoIterator<Item = &'a Element<P, W>>>(&mut self, iter: I) {
+        self.insert_all(iter.into_iter().copied());
+    }
+}
+
+impl<const P: usize, const W: usize> FromIterator<Element<P, W>> for Sketch<P, W> {
+    fn from_iter<I: IntoIterator<Item = Element<P, W>>>(iter: I) -> Self {
+        let mut sketch = Sketch::default();
+        sketch.insert_all(iter);
+        sketch
+    }
+}
+
+impl<'a, const P: usize, const W: usize> FromIterator<&'a Element<P, W>> for Sketch<P, W> {
+    fn from_iter<I: IntoIterator<Item = &'a Element<P, W>>>(iter: I) -> Self {
+        iter.into_iter().copied().collect()
+    }
+}
+
+impl<const P: usize, const W: usize> Sum for Sketch<P, W> {
+    /// Merge all sketches into a new one
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Sketch::default(), |mut acc, sketch| {
+            acc.merge(&sketch);
+            acc
+        })
+    }
+}
+
+impl<'a, const P: usize, const W: usize> Sum<&'a Sketch<P, W>> for Sketch<P, W> {
+    /// Merge all sketches into a new one
+    fn sum<I: Iterator<Item = &'a Self>>(iter: I) -> Self {
+        iter.fold(Sketch::default(), |mut acc, sketch| {
+            acc.merge(sketch);
+            acc
+        })
+    }
+}
+
+impl<const P: usize, const W: usize> Add for Sketch<P, W> {
+    type Output = Self;
+
+    /// Merge `rhs` into this sketch (see `merge`)
+    fn add(mut self, rhs: Self) -> Self {
+        self.merge(&rhs);
+        self
+    }
+}
+
+impl<const P: usize, const W: usize> Add for &Sketch<P, W> {
+    type Output = Sketch<P, W>;
+
+    /// Union of both sketches (see `union`)
+    fn add(self, rhs: Self) -> Sketch<P, W> {
+        self.union(rhs)
+    }
+}
+
+impl<const P: usize, const W: usize> AddAssign for Sketch<P, W> {
+    /// Merge `rhs` into this sketch (see `merge`)
+    fn add_assign(&mut self, rhs: Self) {
+        self.merge(&rhs);
+    }
+}
+
+impl<const P: usize, const W: usize> AddAssign<&Sketch<P, W>> for Sketch<P, W> {
+    /// Merge `rhs` into this sketch (see `merge`)
+    fn add_assign(&mut self, rhs: &Self) {
+        self.merge(rhs);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::hash::BuildHasher;
+    use std::ops::Range;
+    use test_case::test_case;
+    use wyhash::WyHash;
+
+    #[cfg(feature = "with_digest")]
+    #[test]
+    fn test_estimator_with_prefix() {
+        use sha2::Sha256;
+
+        let mut estimator1: Sketch = Sketch::default();
+        assert_eq!(estimator1.estimate(), 0);
+        estimator1.insert(Element::from_digest_with_prefix::<Sha256>(
+            "secret", "hello",
+        ));
+        assert_eq!(estimator1.estimate(), 1);
+
+        let mut estimator2: Sketch = Sketch::default();
+        estimator2.insert(Element::from_digest_with_prefix::<Sha256>(
+            "secret", "hello",
+        ));
+        estimator1.merge(&estimator2);
+        assert_eq!(estimator1.estimate(), 1);
+
+        let mut estimator3: Sketch = Sketch::default();
+        estimator3.insert(Element::from_digest_with_prefix::<Sha256>("sauce", "hello"));
+        estimator1.merge(&estimator3);
+        assert_eq!(estimator1.estimate(), 2);
+    }
+
+    #[cfg(feature = "with_digest")]
+    #[test]
+    fn test_insert_digest_with_prefix() {
+        use sha2::Sha256;
+
+        let mut estimator1: Sketch = Sketch::default();
+        estimator1.insert_digest_with_prefix::<Sha256>("secret", "hello");
+        assert_eq!(estimator1.estimate(), 1);
+
+        let mut estimator2: Sketch = Sketch::default();
+        estimator2.insert(Element::from_digest_with_prefix::<Sha256>(
+            "secret", "hello",
+        ));
+        assert_eq!(estimator1, estimator2);
+        estimator1.merge(&estimator2);
+        assert_eq!(estimator1.estimate(), 1);
+
+        estimator1.insert_digest_with_prefix::<Sha256>("sauce", "hello");
+        assert_eq!(estimator1.estimate(), 2);
+
+        let mut estimator3: Sketch = Sketch::default();
+        estimator3.insert_digest::<Sha256>("hello");
+        assert_eq!(
+            estimator3,
+            Some(Element::from_digest_oneshot::<Sha256>("hello"))
+                .into_iter()
+                .collect()
+        );
+        estimator1.merge(&estimator3);
+        assert_eq!(estimator1.estimate(), 3);
+    }
+
+    #[test]
+    fn small_size() {
+        assert_eq!(Sketch::<0, 0>::default().size_of(), 8);
+    }
+
+    #[test_case(0 => "representation: Small(estimate: 0), avg_err: 0.0000")]
+    #[test_case(1 => "representation: Small(estimate: 1), avg_err: 0.0000")]
+    #[test_case(2 => "representation: Small(estimate: 2), avg_err: 0.0000")]
+    #[test_case(3 => "representation: Array(estimate: 3), avg_err: 0.0000")]
+    #[test_case(4 => "representation: Array(estimate: 4), avg_err: 0.0000")]
+    #[test_case(8 => "representation: Array(estimate: 8), avg_err: 0.0000")]
+    #[test_case(16 => "representation: Array(estimate: 16), avg_err: 0.0000")]
+    #[test_case(17 => "representation: Array(estimate: 17), avg_err: 0.0000")]
     #[test_case(28 => "representation: Array(estimate: 28), avg_err: 0.0000")]
     #[test_case(29 => "representation: Array(estimate: 29), avg_err: 0.0000")]
     #[test_case(56 => "representation: Array(estimate: 56), avg_err: 0.0000")]
     #[test_case(57 => "representation: Array(estimate: 57), avg_err: 0.0000")]
     #[test_case(128 => "representation: Array(estimate: 128), avg_err: 0.0000")]
-    #[test_case(129 => "representation: Hll(estimate: 131), avg_err: 0.0001")]
-    #[test_case(256 => "representation: Hll(estimate: 264), avg_err: 0.0119")]
-    #[test_case(512 => "representation: Hll(estimate: 512), avg_err: 0.0151")]
-    #[test_case(1024 => "representation: Hll(estimate: 1033), avg_err: 0.0172")]
-    #[test_case(10_000 => "representation: Hll(estimate: 10417), avg_err: 0.0281")]
+    #[test_case(129 => "representation: Sparse(estimate: 129), avg_err: 0.0000")]
+    #[test_case(256 => "representation: Hll(estimate: 263), avg_err: 0.0077")]
+    #[test_case(512 => "representation: Hll(estimate: 509), avg_err: 0.0109")]
+    #[test_case(1024 => "representation: Hll(estimate: 1033), avg_err: 0.0144")]
+    #[test_case(10_000 => "representation: Hll(estimate: 10417), avg_err: 0.0278")]
     #[test_case(100_000 => "representation: Hll(estimate: 93099), avg_err: 0.0351")]
     fn test_estimator_p10_w5(n: usize) -> String {
         evaluate_sketch(Sketch::<10, 5>::default(), n)
     }
 
+    // regression: a typo in the p = 11 LogLog-Beta coefficients broke estimates
+    #[test_case(128 => "representation: Array(estimate: 128), avg_err: 0.0000")]
+    #[test_case(256 => "representation: Sparse(estimate: 256), avg_err: 0.0000")]
+    #[test_case(1024 => "representation: Hll(estimate: 1024), avg_err: 0.0025")]
+    #[test_case(10_000 => "representation: Hll(estimate: 10067), avg_err: 0.0091")]
+    #[test_case(100_000 => "representation: Hll(estimate: 94315), avg_err: 0.0219")]
+    fn test_estimator_p11_w6(n: usize) -> String {
+        evaluate_sketch(Sketch::<11, 6>::default(), n)
+    }
+
     #[test_case(0 => "representation: Small(estimate: 0), avg_err: 0.0000")]
     #[test_case(1 => "representation: Small(estimate: 1), avg_err: 0.0000")]
     #[test_case(2 => "representation: Small(estimate: 2), avg_err: 0.0000")]
@@ -189,13 +1453,13 @@ mod tests {
     #[test_case(32 => "representation: Array(estimate: 32), avg_err: 0.0000")]
     #[test_case(64 => "representation: Array(estimate: 64), avg_err: 0.0000")]
     #[test_case(128 => "representation: Array(estimate: 128), avg_err: 0.0000")]
-    #[test_case(129 => "representation: Hll(estimate: 130), avg_err: 0.0001")]
-    #[test_case(256 => "representation: Hll(estimate: 254), avg_err: 0.0029")]
-    #[test_case(512 => "representation: Hll(estimate: 498), avg_err: 0.0068")]
-    #[test_case(1024 => "representation: Hll(estimate: 1012), avg_err: 0.0130")]
-    #[test_case(4096 => "representation: Hll(estimate: 4105), avg_err: 0.0089")]
-    #[test_case(10_000 => "representation: Hll(estimate: 10068), avg_err: 0.0087")]
-    #[test_case(100_000 => "representation: Hll(estimate: 95628), avg_err: 0.0182")]
+    #[test_case(129 => "representation: Sparse(estimate: 129), avg_err: 0.0000")]
+    #[test_case(256 => "representation: Sparse(estimate: 256), avg_err: 0.0000")]
+    #[test_case(512 => "representation: Sparse(estimate: 512), avg_err: 0.0000")]
+    #[test_case(1024 => "representation: Hll(estimate: 1012), avg_err: 0.0036")]
+    #[test_case(4096 => "representation: Hll(estimate: 4105), avg_err: 0.0065")]
+    #[test_case(10_000 => "representation: Hll(estimate: 10068), avg_err: 0.0077")]
+    #[test_case(100_000 => "representation: Hll(estimate: 95628), avg_err: 0.0181")]
     fn test_estimator_p12_w6(n: usize) -> String {
         evaluate_sketch(Sketch::<12, 6>::default(), n)
     }
@@ -210,17 +1474,31 @@ mod tests {
     #[test_case(32 => "representation: Array(estimate: 32), avg_err: 0.0000")]
     #[test_case(64 => "representation: Array(estimate: 64), avg_err: 0.0000")]
     #[test_case(128 => "representation: Array(estimate: 128), avg_err: 0.0000")]
-    #[test_case(129 => "representation: Hll(estimate: 129), avg_err: 0.0000")]
-    #[test_case(256 => "representation: Hll(estimate: 256), avg_err: 0.0000")]
-    #[test_case(512 => "representation: Hll(estimate: 511), avg_err: 0.0004")]
-    #[test_case(1024 => "representation: Hll(estimate: 1022), avg_err: 0.0014")]
-    #[test_case(4096 => "representation: Hll(estimate: 4100), avg_err: 0.0009")]
-    #[test_case(10_000 => "representation: Hll(estimate: 10007), avg_err: 0.0008")]
-    #[test_case(100_000 => "representation: Hll(estimate: 100240), avg_err: 0.0011")]
+    #[test_case(129 => "representation: Sparse(estimate: 129), avg_err: 0.0000")]
+    #[test_case(256 => "representation: Sparse(estimate: 256), avg_err: 0.0000")]
+    #[test_case(512 => "representation: Sparse(estimate: 512), avg_err: 0.0000")]
+    #[test_case(1024 => "representation: Sparse(estimate: 1024), avg_err: 0.0000")]
+    #[test_case(4096 => "representation: Hll(estimate: 4100), avg_err: 0.0005")]
+    #[test_case(10_000 => "representation: Hll(estimate: 10006), avg_err: 0.0006")]
+    #[test_case(100_000 => "representation: Hll(estimate: 100228), avg_err: 0.0010")]
     fn test_estimator_p18_w6(n: usize) -> String {
         evaluate_sketch(Sketch::<18, 6>::default(), n)
     }
 
+    #[test]
+    fn test_small_range_correction_p10() {
+        let mut e = Sketch::<10, 5>::default();
+        e.insert_all((0..128usize).map(Element::from_hasher_default::<WyHash>));
+        let mut total_relative_error = 0.0;
+        for i in 128..512usize {
+            e.insert(Element::from_hasher_default::<WyHash>(i));
+            total_relative_error += e.estimate().abs_diff(i + 1) as f64 / (i + 1) as f64;
+        }
+        // 0.0200 with LogLog-Beta alone
+        let avg_relative_error = total_relative_error / 384.0;
+        assert!(avg_relative_error < 0.018, "{avg_relative_error}");
+    }
+
     fn evaluate_sketch<const P: usize, const W: usize>(mut e: Sketch<P, W>, n: usize) -> String {
         let mut total_relative_error: f64 = 0.0;
         for i in 0..n {
@@ -271,10 +1549,13 @@ mod tests {
     #[test_case(4, 12 => "Array(estimate: 16)")]
     #[test_case(12, 4 => "Array(estimate: 16)")]
     #[test_case(1, 127 => "Array(estimate: 128)")]
-    #[test_case(1, 128 => "Hll(estimate: 130)")]
+    #[test_case(1, 128 => "Sparse(estimate: 129)")]
     #[test_case(127, 1 => "Array(estimate: 128)")]
-    #[test_case(128, 1 => "Hll(estimate: 130)")]
-    #[test_case(128, 128 => "Hll(estimate: 254)")]
+    #[test_case(128, 1 => "Sparse(estimate: 129)")]
+    #[test_case(128, 128 => "Sparse(estimate: 256)")]
+    #[test_case(400, 371 => "Sparse(estimate: 771)")]
+    // the estimate doesn't go below the count at promotion
+    #[test_case(400, 372 => "Hll(estimate: 772)")]
     #[test_case(512, 512 => "Hll(estimate: 1012)")]
     #[test_case(10000, 0 => "Hll(estimate: 10068)")]
     #[test_case(0, 10000 => "Hll(estimate: 10068)")]
@@ -299,6 +1580,1601 @@ mod tests {
         format!("{:?}", lhs)
     }
 
+    #[test_case(0, 0 => "Small(estimate: 0)")]
+    #[test_case(1, 1 => "Small(estimate: 2)")]
+    #[test_case(1, 2 => "Array(estimate: 3)")]
+    #[test_case(2, 1 => "Array(estimate: 3)")]
+    #[test_case(4, 12 => "Array(estimate: 16)")]
+    #[test_case(12, 4 => "Array(estimate: 16)")]
+    #[test_case(1, 128 => "Sparse(estimate: 129)")]
+    #[test_case(128, 1 => "Sparse(estimate: 129)")]
+    #[test_case(400, 400 => "Hll(estimate: 787)")]
+    #[test_case(4, 10000 => "Hll(estimate: 10068)")]
+    #[test_case(10000, 4 => "Hll(estimate: 10068)")]
+    #[test_case(10000, 10000 => "Hll(estimate: 19974)")]
+    fn test_union(lhs_n: usize, rhs_n: usize) -> String {
+        let mut lhs = Sketch::<12, 6>::default();
+        for i in 0..lhs_n {
+            lhs.insert(Element::from_hasher_default::<WyHash>(i));
+        }
+
+        let mut rhs = Sketch::<12, 6>::default();
+        for i in lhs_n..lhs_n + rhs_n {
+            rhs.insert(Element::from_hasher_default::<WyHash>(i));
+        }
+
+        let (lhs_before, rhs_before) = (lhs.clone(), rhs.clone());
+        let union = lhs.union(&rhs);
+        assert_eq!(lhs, lhs_before);
+        assert_eq!(rhs, rhs_before);
+
+        let mut merged = lhs.clone();
+        merged.merge(&rhs);
+        assert_eq!(union, merged);
+
+        format!("{:?}", union)
+    }
+
+    #[test_case(0 => true; "empty small")]
+    #[test_case(1 => false; "populated small")]
+    #[test_case(3 => false; "array")]
+    #[test_case(1000 => false; "hll")]
+    fn test_is_empty(n: usize) -> bool {
+        let mut e = Sketch::<12, 6>::default();
+        for i in 0..n {
+            e.insert(Element::from_hasher_default::<WyHash>(i));
+        }
+        assert_eq!(e.is_empty(), e.estimate() == 0);
+        e.is_empty()
+    }
+
+    #[test]
+    fn test_is_empty_hll() {
+        assert!(Sketch::<12, 6>::Hll(HyperLogLog::new(&[])).is_empty());
+    }
+
+    #[test_case(0; "small")]
+    #[test_case(10; "array")]
+    #[test_case(10_000; "hll")]
+    fn test_clear(n: usize) {
+        let mut e = Sketch::<12, 6>::default();
+        for i in 0..n {
+            e.insert(Element::from_hasher_default::<WyHash>(i));
+        }
+        e.clear();
+        assert_eq!(e.estimate(), 0);
+        assert_eq!(e, Sketch::default());
+
+        let mut fresh = Sketch::<12, 6>::default();
+        for i in 0..1000 {
+            e.insert(Element::from_hasher_default::<WyHash>(i));
+            fresh.insert(Element::from_hasher_default::<WyHash>(i));
+        }
+        assert_eq!(e, fresh);
+        assert_eq!(e.estimate(), fresh.estimate());
+    }
+
+    #[test_case(0; "empty")]
+    #[test_case(2; "small")]
+    #[test_case(100; "array")]
+    #[test_case(100_000; "hll")]
+    fn test_insert_all(n: usize) {
+        let mut batch = Sketch::<12, 6>::default();
+        batch.insert_all((0..n).map(Element::from_hasher_default::<WyHash>));
+
+        let mut each = Sketch::<12, 6>::default();
+        for i in 0..n {
+            each.insert(Element::from_hasher_default::<WyHash>(i));
+        }
+
+        assert_eq!(batch, each);
+        assert_eq!(batch.estimate(), each.estimate());
+    }
+
+    #[test]
+    fn test_from_iter_range() {
+        let collected: Sketch = (0..1000)
+            .map(Element::from_hasher_default::<WyHash>)
+            .collect();
+
+        let mut manual = Sketch::default();
+        for i in 0..1000 {
+            manual.insert(Element::from_hasher_default::<WyHash>(i));
+        }
+        assert_eq!(collected, manual);
+    }
+
+    #[test]
+    fn test_extend_filtered() {
+        let elements: Vec<Element> = (0..1000)
+            .map(Element::from_hasher_default::<WyHash>)
+            .collect();
+        let mut extended: Sketch = elements.iter().take(10).collect();
+        extended.extend(elements.iter().skip(10).step_by(3));
+
+        let mut manual = Sketch::default();
+        for (i, &element) in elements.iter().enumerate() {
+            if i < 10 || (i - 10) % 3 == 0 {
+                manual.insert(element);
+            }
+        }
+        assert_eq!(extended, manual);
+        assert_eq!(extended.estimate(), manual.estimate());
+    }
+
+    #[test_case(0 => 0.0; "empty")]
+    #[test_case(2 => 0.0; "small")]
+    #[test_case(100 => 0.0; "array")]
+    fn test_error_bound_exact(n: usize) -> f64 {
+        let mut e = Sketch::<12, 6>::default();
+        e.insert_all((0..n).map(Element::from_hasher_default::<WyHash>));
+        let (estimate, bound) = e.estimate_with_error_bound();
+        assert_eq!(estimate, n);
+        bound
+    }
+
+    #[test]
+    fn test_error_bound_hll() {
+        let mut e = Sketch::<12, 6>::default();
+        e.insert_all((0..10_000usize).map(Element::from_hasher_default::<WyHash>));
+        let (estimate, bound) = e.estimate_with_error_bound();
+        assert_eq!(estimate, e.estimate());
+        // 1.96 * 1.04 / 64 ~= 3.2%
+        assert!(bound > 0.03 * estimate as f64 && bound < 0.033 * estimate as f64);
+        assert!(estimate.abs_diff(10_000) as f64 <= bound);
+    }
+
+    #[test_case(0 => Confidence::Exact; "empty")]
+    #[test_case(2 => Confidence::Exact; "small")]
+    #[test_case(100 => Confidence::Exact; "array")]
+    #[test_case(1_000 => Confidence::Normal; "hll")]
+    fn test_estimate_checked(n: usize) -> Confidence {
+        let mut e = Sketch::<12, 6>::default();
+        e.insert_all((0..n).map(Element::from_hasher_default::<WyHash>));
+        let (estimate, confidence) = e.estimate_checked();
+        assert_eq!(estimate, e.estimate());
+        confidence
+    }
+
+    #[test]
+    fn test_estimate_checked_saturated() {
+        // 16 registers of 4 bits saturate above a few thousand elements
+        let mut e = Sketch::<4, 4>::default();
+        e.insert_all((0..100_000usize).map(Element::from_hasher_default::<WyHash>));
+        assert!(e.estimate() as u64 > Sketch::<4, 4>::max_reliable_cardinality());
+        assert_eq!(e.estimate_checked().1, Confidence::Degraded);
+    }
+
+    #[test_case(0; "empty")]
+    #[test_case(2; "small")]
+    #[test_case(100; "array")]
+    fn test_estimate_range_exact(n: usize) {
+        let mut e = Sketch::<12, 6>::default();
+        e.insert_all((0..n).map(Element::from_hasher_default::<WyHash>));
+        assert_eq!(e.estimate_range(1.96), (n, n, n));
+    }
+
+    #[test]
+    fn test_estimate_range_hll() {
+        let mut e = Sketch::<12, 6>::default();
+        e.insert_all((0..10_000usize).map(Element::from_hasher_default::<WyHash>));
+        let (lower, point, upper) = e.estimate_range(1.96);
+        assert_eq!(point, e.estimate());
+        assert!(lower < point && point < upper);
+        assert!((lower..=upper).contains(&10_000));
+
+        let (_, bound) = e.estimate_with_error_bound();
+        assert_eq!(upper - point, libm::ceil(bound) as usize);
+
+        // wider intervals for higher confidence
+        let (wide_lower, _, wide_upper) = e.estimate_range(3.0);
+        assert!(wide_lower < lower && upper < wide_upper);
+    }
+
+    #[test_case(0; "empty")]
+    #[test_case(2; "small")]
+    #[test_case(100; "array")]
+    #[test_case(129; "promoted")]
+    #[test_case(10_000; "hll")]
+    fn test_estimate_f64(n: usize) {
+        let e: Sketch = (0..n).map(Element::from_hasher_default::<WyHash>).collect();
+        let estimate = e.estimate_f64();
+        assert_eq!(estimate.round() as usize, e.estimate());
+        if n <= 129 {
+            assert_eq!(estimate, n as f64);
+        } else {
+            assert_ne!(estimate.fract(), 0.0);
+        }
+    }
+
+    #[test]
+    fn test_estimate_f64_saturated() {
+        // past the saturation threshold of `W = 4`, estimates come from the MLE
+        let e: Sketch<12, 4> = (0..1_000_000usize)
+            .map(Element::from_hasher_default::<WyHash>)
+            .collect();
+        let estimate = e.estimate_f64();
+        assert_eq!(estimate.round() as usize, e.estimate());
+        assert_ne!(estimate.fract(), 0.0);
+    }
+
+    #[test]
+    fn test_shrink_to_fit() {
+        let elements: Vec<Element> = (0..200usize)
+            .map(Element::from_hasher_default::<WyHash>)
+            .collect();
+        let mut e: Sketch = elements[..100].iter().copied().collect();
+        let items: Vec<u32> = e.iter_sparse().unwrap().collect();
+        let heap_size = e.heap_size();
+
+        e.shrink_to_fit();
+        assert!(e.heap_size() < heap_size);
+        assert_eq!(e.estimate(), 100);
+        assert_eq!(e.iter_sparse().unwrap().collect::<Vec<_>>(), items);
+
+        // duplicates are still found, and new items regrow the storage
+        e.insert_all(elements[..100].iter().copied());
+        assert_eq!(e.estimate(), 100);
+        e.insert_all(elements[100..128].iter().copied());
+        assert_eq!(e.representation_kind(), RepresentationKind::Array);
+        assert_eq!(e.estimate(), 128);
+        e.insert(elements[128]);
+        assert_eq!(e.representation_kind(), RepresentationKind::Sparse);
+        assert_eq!(e.estimate(), 129);
+
+        // the sparse hashes are sorted and shrunk the same way
+        e.insert_all(elements[129..].iter().copied());
+        let heap_size = e.heap_size();
+        e.shrink_to_fit();
+        assert!(e.heap_size() < heap_size);
+        assert_eq!(e, elements.iter().collect());
+        assert_eq!(e.estimate(), 200);
+    }
+
+    #[test]
+    fn test_recompute_cached() {
+        let mut e: Sketch = (0..10_000usize)
+            .map(Element::from_hasher_default::<WyHash>)
+            .collect();
+        let estimate = e.estimate();
+        let Sketch::Hll(hll) = &mut e else {
+            panic!("expected a Hll representation");
+        };
+        hll.histogram[0] -= 1;
+        hll.histogram[1] += 1;
+        assert_ne!(e.estimate(), estimate);
+
+        e.recompute_cached();
+        assert_eq!(e.estimate(), estimate);
+
+        let mut array: Sketch = (0..100usize)
+            .map(Element::from_hasher_default::<WyHash>)
+            .collect();
+        let expected = array.clone();
+        array.recompute_cached();
+        assert_eq!(array, expected);
+    }
+
+    #[test_case(2; "small")]
+    #[test_case(100; "array")]
+    fn test_probably_contains_exact(n: usize) {
+        let elements: Vec<Element> = (0..2 * n)
+            .map(Element::from_hasher_default::<WyHash>)
+            .collect();
+        let e: Sketch = elements[..n].iter().collect();
+        assert!(elements[..n].iter().all(|&el| e.probably_contains(el)));
+        assert!(!elements[n..].iter().any(|&el| e.probably_contains(el)));
+    }
+
+    #[test]
+    fn test_probably_contains_hll() {
+        let elements: Vec<Element> = (0..2_000usize)
+            .map(Element::from_hasher_default::<WyHash>)
+            .collect();
+        let e: Sketch = elements[..1_000].iter().collect();
+        assert!(elements[..1_000].iter().all(|&el| e.probably_contains(el)));
+        // with 4096 registers, most registers are still empty
+        let false_positives = elements[1_000..]
+            .iter()
+            .filter(|&&el| e.probably_contains(el))
+            .count();
+        assert!(false_positives < 500, "{false_positives} false positives");
+    }
+
+    #[test_case(0 => Some(3); "empty")]
+    #[test_case(1 => Some(2); "one")]
+    #[test_case(2 => Some(1); "two")]
+    #[test_case(3 => Some(126); "smallest array")]
+    #[test_case(127 => Some(2); "127")]
+    #[test_case(128 => Some(1); "full array")]
+    #[test_case(129 => Some(643); "smallest sparse")]
+    #[test_case(771 => Some(1); "full sparse")]
+    #[test_case(772 => None; "hll")]
+    fn test_distinct_until_upgrade(n: usize) -> Option<usize> {
+        let mut sketch: Sketch<12, 6> =
+            (0..n).map(Element::from_hasher_default::<WyHash>).collect();
+        let headroom = sketch.distinct_until_upgrade();
+        // the returned number of new elements triggers an upgrade, on the last one
+        if let Some(headroom) = headroom {
+            let upgrades: Vec<_> = (n..n + headroom)
+                .map(|i| sketch.insert_returning_upgrade(Element::from_hasher_default::<WyHash>(i)))
+                .collect();
+            assert!(upgrades[..headroom - 1].iter().all(Option::is_none));
+            assert!(upgrades[headroom - 1].is_some());
+        }
+        headroom
+    }
+
+    #[test]
+    fn test_is_exact() {
+        let mut sketch = Sketch::<12, 6>::default();
+        assert!(sketch.is_exact());
+        for i in 0..1000usize {
+            let upgrade =
+                sketch.insert_returning_upgrade(Element::from_hasher_default::<WyHash>(i));
+            if i < 771 {
+                assert!(sketch.is_exact());
+                assert_eq!(sketch.estimate(), i + 1);
+            } else {
+                assert!(!sketch.is_exact());
+            }
+            match i {
+                128 => assert_eq!(upgrade, Some(RepresentationKind::Sparse)),
+                771 => assert_eq!(upgrade, Some(RepresentationKind::Hll)),
+                _ => {}
+            }
+        }
+    }
+
+    #[test_case(0..0, 0..0; "empty")]
+    #[test_case(0..2, 1..100; "small and array")]
+    #[test_case(0..100, 50..10_000; "array and hll")]
+    #[test_case(0..5_000, 2_000..10_000; "hll and hll")]
+    fn test_approx_eq_merge_order(a: Range<usize>, b: Range<usize>) {
+        let a: Sketch = a.map(Element::from_hasher_default::<WyHash>).collect();
+        let b: Sketch = b.map(Element::from_hasher_default::<WyHash>).collect();
+        let mut ab = a.clone();
+        ab.merge(&b);
+        let mut ba = b.clone();
+        ba.merge(&a);
+        assert!(ab.approx_eq(&ba, 0.0));
+        assert!(ba.approx_eq(&ab, 0.0));
+    }
+
+    #[test]
+    fn test_approx_eq_different_registers() {
+        // the same items, hashed with another seed
+        let sketch = |seed: u8| -> Sketch {
+            (0..10_000usize)
+                .map(|i| Element::from_hasher_default::<WyHash>((seed, i)))
+                .collect()
+        };
+        let (a, b) = (sketch(0), sketch(1));
+        assert_ne!(a, b);
+        assert!(a.approx_eq(&b, 0.05));
+        assert!(b.approx_eq(&a, 0.05));
+
+        let c: Sketch = (0..12_000usize)
+            .map(|i| Element::from_hasher_default::<WyHash>((0u8, i)))
+            .collect();
+        assert!(!a.approx_eq(&c, 0.05));
+        assert!(!a.approx_eq(&b, 0.0));
+    }
+
+    #[test_case(0, 0; "empty")]
+    #[test_case(2, 100; "small and array")]
+    #[test_case(100, 10_000; "array and hll")]
+    #[test_case(10_000, 2; "hll and small")]
+    fn test_add(lhs_n: usize, rhs_n: usize) {
+        let a: Sketch = (0..lhs_n)
+            .map(Element::from_hasher_default::<WyHash>)
+            .collect();
+        let b: Sketch = (lhs_n / 2..lhs_n / 2 + rhs_n)
+            .map(Element::from_hasher_default::<WyHash>)
+            .collect();
+        let mut merged = a.clone();
+        merged.merge(&b);
+
+        assert_eq!(&a + &b, merged);
+        assert_eq!(a.clone() + b.clone(), merged);
+        let mut c = a.clone();
+        c += &b;
+        assert_eq!(c, merged);
+        let mut c = a;
+        c += b;
+        assert_eq!(c, merged);
+    }
+
+    #[test_case(0; "empty")]
+    #[test_case(2; "small")]
+    #[test_case(100; "array")]
+    #[test_case(10_000; "hll")]
+    fn test_insert_hashes(n: usize) {
+        let hasher = core::hash::BuildHasherDefault::<WyHash>::default();
+        let hashes: Vec<u64> = (0..n).map(|i| hasher.hash_one(i)).collect();
+        // start from a sketch that already holds some of the hashes
+        let start = Sketch::<12, 6>::from_hashes(hashes.iter().copied().step_by(3));
+
+        let mut bulk = start.clone();
+        bulk.insert_hashes(hashes.iter().copied());
+        let mut one_by_one = start;
+        for &h in &hashes {
+            one_by_one.insert(Element::from_hashed(h));
+        }
+        assert_eq!(bulk, one_by_one);
+        assert_eq!(bulk.estimate(), one_by_one.estimate());
+    }
+
+    #[test_case(0; "empty")]
+    #[test_case(100; "array")]
+    #[test_case(10_000; "hll")]
+    fn test_insert_hashes_le(n: usize) {
+        let hasher = core::hash::BuildHasherDefault::<WyHash>::default();
+        let hashes: Vec<u64> = (0..n).map(|i| hasher.hash_one(i)).collect();
+        let bytes: Vec<u8> = hashes.iter().flat_map(|h| h.to_le_bytes()).collect();
+
+        let mut e = Sketch::<12, 6>::default();
+        e.insert_hashes_le(&bytes).unwrap();
+        assert_eq!(e, Sketch::from_hashes(hashes));
+
+        assert_eq!(
+            e.insert_hashes_le(&[0; 12]),
+            Err(HashBytesError { len: 12 })
+        );
+    }
+
+    #[test]
+    fn test_sort_by_estimate() {
+        let sketch = |range: Range<usize>| -> Sketch {
+            range.map(Element::from_hasher_default::<WyHash>).collect()
+        };
+        // ties keep their order
+        let mut sketches = vec![
+            sketch(0..10_000),
+            sketch(0..5),
+            sketch(0..100),
+            sketch(5..10),
+        ];
+        sketches.sort_by_key(Sketch::estimate);
+        assert_eq!(
+            sketches,
+            [
+                sketch(0..5),
+                sketch(5..10),
+                sketch(0..100),
+                sketch(0..10_000)
+            ]
+        );
+    }
+
+    /// Build a sketch from `quickcheck` hashes, in a representation picked by
+    /// `kind`
+    #[cfg(not(target_arch = "wasm32"))]
+    fn arbitrary_sketch((hashes, kind): (Vec<u64>, u8)) -> Sketch {
+        let len = match kind % 3 {
+            0 => hashes.len().min(2),
+            1 => hashes.len().min(128),
+            _ => hashes.len(),
+        };
+        hashes[..len]
+            .iter()
+            .map(|&h| Element::from_hashed(h))
+            .collect()
+    }
+
+    /// Sketches are equal, and so are their estimates and hashes
+    #[cfg(not(target_arch = "wasm32"))]
+    fn identical(lhs: &Sketch, rhs: &Sketch) -> bool {
+        let hasher = core::hash::BuildHasherDefault::<WyHash>::default();
+        lhs == rhs
+            && lhs.estimate_f64().to_bits() == rhs.estimate_f64().to_bits()
+            && hasher.hash_one(lhs) == hasher.hash_one(rhs)
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_merge_commutative() {
+        fn prop(a: (Vec<u64>, u8), b: (Vec<u64>, u8)) -> bool {
+            let (a, b) = (arbitrary_sketch(a), arbitrary_sketch(b));
+            let mut ab = a.clone();
+            ab.merge(&b);
+            let mut ba = b.clone();
+            ba.merge(&a);
+            identical(&ab, &ba)
+        }
+        quickcheck::QuickCheck::new()
+            .rng(quickcheck::Gen::new(400))
+            .quickcheck(prop as fn(_, _) -> bool);
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_merge_associative() {
+        fn prop(a: (Vec<u64>, u8), b: (Vec<u64>, u8), c: (Vec<u64>, u8)) -> bool {
+            let (a, b, c) = (
+                arbitrary_sketch(a),
+                arbitrary_sketch(b),
+                arbitrary_sketch(c),
+            );
+            let mut ab_c = a.clone();
+            ab_c.merge(&b);
+            ab_c.merge(&c);
+            let mut bc = b.clone();
+            bc.merge(&c);
+            let mut a_bc = a.clone();
+            a_bc.merge(&bc);
+            identical(&ab_c, &a_bc) && identical(&ab_c, &Sketch::merge_all(&[a, b, c]))
+        }
+        quickcheck::QuickCheck::new()
+            .rng(quickcheck::Gen::new(400))
+            .quickcheck(prop as fn(_, _, _) -> bool);
+    }
+
+    #[test]
+    fn test_promoted_count() {
+        let mut e = Sketch::<12, 6>::default();
+        e.insert_all((0..772usize).map(Element::from_hasher_default::<WyHash>));
+        assert_eq!(e.representation_kind(), RepresentationKind::Hll);
+        // the registers alone estimate 760
+        assert_eq!(e.estimate(), 772);
+
+        // inserts never drop the estimate below the count at promotion
+        let mut previous = e.estimate();
+        for i in (0..100usize).chain(772..2000) {
+            e.insert(Element::from_hasher_default::<WyHash>(i));
+            assert!(e.estimate() >= previous.min(772));
+            previous = e.estimate();
+        }
+        assert_eq!(e.estimate(), 1969);
+    }
+
+    #[cfg(feature = "with_serde")]
+    #[test]
+    fn test_promoted_count_round_trip() {
+        let a: Sketch = (0..772usize)
+            .map(Element::from_hasher_default::<WyHash>)
+            .collect();
+        let b: Sketch = serde_json::from_str(&serde_json::to_string(&a).unwrap()).unwrap();
+        assert_eq!(a, b);
+        assert_eq!(a.estimate(), b.estimate());
+
+        // the compact form stores the count before the packed registers
+        let compact = crate::serde::CompactSketch(a.clone());
+        let bytes = postcard::to_allocvec(&compact).unwrap();
+        assert_eq!(bytes.len(), 4 + 3072 + 3);
+        let b: crate::serde::CompactSketch = postcard::from_bytes(&bytes).unwrap();
+        assert_eq!(b.0, a);
+        assert_eq!(b.0.estimate(), 772);
+    }
+
+    #[test]
+    fn test_promoted_count_eq() {
+        let mut promoted: Sketch = (0..772usize)
+            .map(Element::from_hasher_default::<WyHash>)
+            .collect();
+        let mut loaded = Sketch::<12, 6>::default();
+        let Sketch::Hll(hll) = &promoted else {
+            panic!("expected hll");
+        };
+        loaded.load_hll_registers(&hll.registers).unwrap();
+        // same registers, but only `promoted` knows it has at least 772 elements
+        assert_ne!(promoted, loaded);
+        assert_eq!([promoted.estimate(), loaded.estimate()], [772, 760]);
+
+        // once the registers estimate more, the count makes no difference
+        let more = (772..2000usize).map(Element::from_hasher_default::<WyHash>);
+        for element in more {
+            promoted.insert(element);
+            loaded.insert(element);
+        }
+        assert_eq!(promoted, loaded);
+        let hash =
+            |sketch: &Sketch| std::hash::BuildHasherDefault::<WyHash>::default().hash_one(sketch);
+        assert_eq!(hash(&promoted), hash(&loaded));
+    }
+
+    #[test_case(0..10_000, 0..10_000, 10_000; "full overlap")]
+    #[test_case(0..10_000, 10_000..20_000, 0; "disjoint")]
+    #[test_case(0..10_000, 5_000..15_000, 5_000; "half overlap")]
+    #[test_case(0..10, 5..15, 5; "exact")]
+    fn test_estimate_intersection(a: Range<usize>, b: Range<usize>, expected: usize) {
+        let a: Sketch = a.map(Element::from_hasher_default::<WyHash>).collect();
+        let b: Sketch = b.map(Element::from_hasher_default::<WyHash>).collect();
+        let intersection = a.estimate_intersection(&b);
+        assert_eq!(intersection, b.estimate_intersection(&a));
+
+        // three standard errors of the union estimate
+        let band = 3.0 * 1.04 / 64.0 * a.union(&b).estimate() as f64;
+        assert!(
+            intersection.abs_diff(expected) as f64 <= band,
+            "intersection {intersection} too far from {expected}"
+        );
+    }
+
+    #[cfg(feature = "theta")]
+    #[test_case(0..10, 5..15, 5; "small")]
+    #[test_case(0..100, 50..150, 50; "array")]
+    #[test_case(0..10_000, 10_000..20_000, 0; "disjoint")]
+    #[test_case(0..10_000, 5_000..15_000, 5_000; "half overlap")]
+    #[test_case(0..10_000, 0..10_000, 10_000; "full overlap")]
+    fn test_theta_intersection(a: Range<usize>, b: Range<usize>, expected: usize) {
+        let a: Sketch = a.map(Element::from_hasher_default::<WyHash>).collect();
+        let b: Sketch = b.map(Element::from_hasher_default::<WyHash>).collect();
+        let intersection = a.theta_intersection(&b).unwrap();
+        assert_eq!(Some(intersection), b.theta_intersection(&a));
+        if a.is_exact() && b.is_exact() {
+            assert_eq!(intersection, expected);
+        }
+
+        // three standard errors of a sample of 1024 hashes of the union
+        let union = a.union(&b).estimate() as f64;
+        let band = 3.0 * (expected as f64 * union / 1024.0).sqrt();
+        assert!(
+            intersection.abs_diff(expected) as f64 <= band,
+            "intersection {intersection} too far from {expected}"
+        );
+    }
+
+    #[cfg(feature = "theta")]
+    #[test]
+    fn test_theta_intersection_small_overlap() {
+        // 20,000 elements per set, overlapping by 200
+        let (mut theta_error, mut inclusion_exclusion_error) = (0, 0);
+        for trial in 0..10 {
+            let start = trial * 100_000;
+            let a: Sketch = (start..start + 20_000)
+                .map(Element::from_hasher_default::<WyHash>)
+                .collect();
+            let b: Sketch = (start + 19_800..start + 39_800)
+                .map(Element::from_hasher_default::<WyHash>)
+                .collect();
+            theta_error += a.theta_intersection(&b).unwrap().abs_diff(200);
+            inclusion_exclusion_error += a.estimate_intersection(&b).abs_diff(200);
+        }
+        assert!(
+            theta_error * 2 < inclusion_exclusion_error,
+            "theta error {theta_error}, inclusion-exclusion error {inclusion_exclusion_error}"
+        );
+    }
+
+    #[cfg(feature = "theta")]
+    #[test]
+    fn test_theta_intersection_lost_sample() {
+        let a: Sketch = (0..10_000usize)
+            .map(Element::from_hasher_default::<WyHash>)
+            .collect();
+        let mut b = a.clone();
+        b.merge(&a);
+        assert!(a.theta_intersection(&b).is_some());
+
+        // registers from outside a sketch don't come with a sample
+        let Sketch::Hll(hll) = &a else {
+            panic!("expected the Hll representation");
+        };
+        let mut loaded = Sketch::default();
+        loaded.load_hll_registers(&hll.registers).unwrap();
+        assert_eq!(loaded.theta_intersection(&a), None);
+        b.merge(&loaded);
+        assert_eq!(a.theta_intersection(&b), None);
+    }
+
+    #[cfg(feature = "minhash")]
+    #[test_case(500; "sparse")]
+    #[test_case(10_000; "hll")]
+    fn test_minhash_similarity(n: usize) {
+        use crate::minhash::signature_similarity;
+
+        let a: Sketch = (0..n).map(Element::from_hasher_default::<WyHash>).collect();
+        let signature = a.minhash_signature().unwrap();
+        let mut previous = 1.0;
+        for shift in [0, n / 10, n / 4, n / 2, 3 * n / 4, n] {
+            let b: Sketch = (shift..n + shift)
+                .map(Element::from_hasher_default::<WyHash>)
+                .collect();
+            let jaccard = (n - shift) as f64 / (n + shift) as f64;
+            let similarity = signature_similarity(&signature, &b.minhash_signature().unwrap());
+            // three standard errors of 128 bins
+            assert!(
+                (similarity - jaccard).abs() <= 3.0 * (0.25f64 / 128.0).sqrt(),
+                "similarity {similarity} too far from {jaccard}"
+            );
+            assert!(similarity <= previous);
+            previous = similarity;
+
+            // the bins of a union are the smallest of both
+            let union: Sketch = (0..n + shift)
+                .map(Element::from_hasher_default::<WyHash>)
+                .collect();
+            assert_eq!(
+                a.union(&b).minhash_signature(),
+                union.minhash_signature(),
+                "shift {shift}"
+            );
+        }
+    }
+
+    #[cfg(feature = "minhash")]
+    #[test]
+    fn test_minhash_lost_bins() {
+        let a: Sketch = (0..10_000usize)
+            .map(Element::from_hasher_default::<WyHash>)
+            .collect();
+        let Sketch::Hll(hll) = &a else {
+            panic!("expected the Hll representation");
+        };
+        let mut loaded = Sketch::default();
+        loaded.load_hll_registers(&hll.registers).unwrap();
+        assert_eq!(loaded.minhash_signature(), None);
+        let mut b = a.clone();
+        b.merge(&loaded);
+        assert_eq!(b.minhash_signature(), None);
+    }
+
+    #[test_case(0..10_000, 0..5_000, 5_000; "superset")]
+    #[test_case(0..10_000, 10_000..20_000, 10_000; "disjoint")]
+    #[test_case(0..10_000, 5_000..15_000, 5_000; "partial overlap")]
+    #[test_case(0..10_000, 0..20_000, 0; "subset")]
+    #[test_case(0..10, 5..15, 5; "exact")]
+    fn test_estimate_difference(a: Range<usize>, b: Range<usize>, expected: usize) {
+        let a: Sketch = a.map(Element::from_hasher_default::<WyHash>).collect();
+        let b: Sketch = b.map(Element::from_hasher_default::<WyHash>).collect();
+        let difference = a.estimate_difference(&b);
+
+        // three standard errors of each of the union and `a` estimates
+        let band = 3.0 * 1.04 / 64.0 * (a.union(&b).estimate() + a.estimate()) as f64;
+        assert!(
+            difference.abs_diff(expected) as f64 <= band,
+            "difference {difference} too far from {expected}"
+        );
+    }
+
+    #[test_case(0..0, 0..0, 0.0; "empty")]
+    #[test_case(0..10_000, 0..10_000, 1.0; "identical")]
+    #[test_case(0..10_000, 10_000..20_000, 0.0; "disjoint")]
+    #[test_case(0..10_000, 5_000..15_000, 1.0 / 3.0; "half overlap")]
+    #[test_case(0..10, 5..15, 1.0 / 3.0; "exact")]
+    fn test_jaccard(a: Range<usize>, b: Range<usize>, expected: f64) {
+        let a: Sketch = a.map(Element::from_hasher_default::<WyHash>).collect();
+        let b: Sketch = b.map(Element::from_hasher_default::<WyHash>).collect();
+        let jaccard = a.jaccard(&b);
+        assert_eq!(jaccard, b.jaccard(&a));
+        assert!((0.0..=1.0).contains(&jaccard));
+        assert!(
+            (jaccard - expected).abs() < 0.05,
+            "jaccard {jaccard} too far from {expected}"
+        );
+    }
+
+    #[test_case(&[] => "Small(estimate: 0)"; "empty")]
+    #[test_case(&[0..1, 1..2] => "Small(estimate: 2)"; "small")]
+    #[test_case(&[0..10, 5..20, 15..30] => "Array(estimate: 30)"; "array")]
+    #[test_case(&[0..10, 0..1000, 500..5000] => "Hll(estimate: 5012)"; "hll")]
+    fn test_sum(parts: &[Range<usize>]) -> String {
+        let parts: Vec<Sketch> = parts
+            .iter()
+            .map(|r| {
+                r.clone()
+                    .map(Element::from_hasher_default::<WyHash>)
+                    .collect()
+            })
+            .collect();
+
+        let mut sequential = Sketch::default();
+        for part in &parts {
+            sequential.merge(part);
+        }
+
+        let by_ref: Sketch = parts.iter().sum();
+        let owned: Sketch = parts.into_iter().sum();
+        assert_eq!(by_ref, sequential);
+        assert_eq!(owned, sequential);
+
+        format!("{:?}", owned)
+    }
+
+    #[test_case(0; "from small")]
+    #[test_case(50; "from array")]
+    #[test_case(5_000; "from hll")]
+    fn test_merge_many(n: usize) {
+        let init: Sketch = (0..n).map(Element::from_hasher_default::<WyHash>).collect();
+        let others: Vec<Sketch> = (0..50)
+            .map(|i| {
+                let size = [0, 1, 2, 10, 100, 1_000, 10_000][i % 7];
+                (i * 1_000..i * 1_000 + size)
+                    .map(Element::from_hasher_default::<WyHash>)
+                    .collect()
+            })
+            .collect();
+
+        let mut sequential = init.clone();
+        for other in &others {
+            sequential.merge(other);
+        }
+
+        let mut many = init.clone();
+        many.merge_many(&others);
+        assert_eq!(many, sequential);
+        assert_eq!(many.estimate(), sequential.estimate());
+    }
+
+    #[test]
+    fn test_merge_from_filtered_map() {
+        use std::collections::HashMap;
+
+        let by_region: HashMap<String, Sketch> = (0..20usize)
+            .map(|i| {
+                let size = [0, 2, 100, 500, 5_000][i % 5];
+                let sketch = (i * 10_000..i * 10_000 + size)
+                    .map(Element::from_hasher_default::<WyHash>)
+                    .collect();
+                (format!("{}-{i}", ["eu", "us"][i % 2]), sketch)
+            })
+            .collect();
+        let in_eu = |region: &String| region.starts_with("eu-");
+
+        let mut expected = Sketch::default();
+        for (region, sketch) in &by_region {
+            if in_eu(region) {
+                expected.merge(sketch);
+            }
+        }
+
+        let mut merged = Sketch::default();
+        merged.merge_from(
+            (by_region.iter())
+                .filter(|(region, _)| in_eu(region))
+                .map(|(_, sketch)| sketch),
+        );
+        assert_eq!(merged, expected);
+        assert_eq!(merged.estimate(), expected.estimate());
+
+        let mut unchanged = expected.clone();
+        unchanged.merge_from(by_region.values().filter(|sketch| sketch.is_empty()));
+        assert_eq!(unchanged, expected);
+    }
+
+    #[test_case(&[0..1, 1..2] => "Small(estimate: 2)"; "small")]
+    #[test_case(&[0..10, 5..20, 15..30] => "Array(estimate: 30)"; "array")]
+    fn test_merge_many_sparse(parts: &[Range<usize>]) -> String {
+        let parts: Vec<Sketch> = parts
+            .iter()
+            .map(|r| {
+                r.clone()
+                    .map(Element::from_hasher_default::<WyHash>)
+                    .collect()
+            })
+            .collect();
+        let mut many = Sketch::default();
+        many.merge_many(&parts);
+        assert_eq!(many, parts.iter().sum());
+        format!("{:?}", many)
+    }
+
+    #[test]
+    fn test_hll_registers() {
+        let elements: Vec<Element<10, 5>> = (0..10_000)
+            .map(Element::from_hasher_default::<WyHash>)
+            .collect();
+        let mut e = Sketch::<10, 5>::default();
+        e.insert_all(elements[..100].iter().copied());
+        assert_eq!(e.hll_registers(), None);
+
+        e.insert_all(elements.iter().copied());
+        let registers = e.hll_registers().unwrap();
+        assert_eq!(registers.len(), 1 << 10);
+        let max_rank = elements.iter().map(|e| e.0 & ((1 << 5) - 1)).max();
+        assert_eq!(registers.iter().max().map(|&r| u32::from(r)), max_rank);
+    }
+
+    #[test_case(2; "into small")]
+    #[test_case(10_000; "into hll")]
+    fn test_load_hll_registers(n: usize) {
+        let source: Sketch = (0..5_000usize)
+            .map(Element::from_hasher_default::<WyHash>)
+            .collect();
+        let Sketch::Hll(hll) = &source else {
+            panic!("expected hll representation");
+        };
+        let mut e: Sketch = (0..n).map(Element::from_hasher_default::<WyHash>).collect();
+        e.load_hll_registers(&hll.registers).unwrap();
+        assert_eq!(e, source);
+        assert_eq!(e.estimate(), source.estimate());
+    }
+
+    #[test]
+    fn test_load_hll_registers_invalid() {
+        let mut e: Sketch = (0..100usize)
+            .map(Element::from_hasher_default::<WyHash>)
+            .collect();
+        let original = e.clone();
+        let len = HyperLogLog::<12, 6>::HLL_SLICE_LEN;
+
+        assert_eq!(
+            e.load_hll_registers(&vec![0; len - 1]),
+            Err(LoadError::InvalidLength {
+                expected: len,
+                found: len - 1
+            })
+        );
+        // rank 63 in register 1, above the 53 a 64-bit hash allows for P = 12
+        let mut registers = vec![0; len];
+        registers[0] = 63 << 6;
+        assert_eq!(
+            e.load_hll_registers(&registers),
+            Err(LoadError::InvalidRank { index: 1, rank: 63 })
+        );
+        assert_eq!(e, original);
+    }
+
+    #[test_case(0; "empty")]
+    #[test_case(1; "one element")]
+    #[test_case(2; "two elements")]
+    fn test_from_small_word(n: usize) {
+        let elements: Vec<Element> = (0..n).map(Element::from_hasher_default::<WyHash>).collect();
+        let data = (elements.iter().zip([2, 33])).fold(0, |data, (e, shift)| {
+            data | (u64::from(e.encoded()) << shift)
+        });
+        let source: Sketch = elements.into_iter().collect();
+        let e = Sketch::from_small_word(data).unwrap();
+        assert_eq!(e, source);
+        assert_eq!(e.estimate(), n);
+    }
+
+    #[test_case(0b01; "array type bits")]
+    #[test_case(0b10; "hll type bits")]
+    #[test_case(1 << 33; "second slot without first")]
+    #[test_case((1 << 2) | (1 << 33); "duplicate hashes")]
+    #[test_case(1 << 8; "zero rank in first slot")]
+    #[test_case((1 << 2) | (1 << 39); "zero rank in second slot")]
+    fn test_from_small_word_malformed(data: u64) {
+        assert_eq!(
+            Sketch::<12, 6>::from_small_word(data),
+            Err(ParseError::InvalidSmallWord(data))
+        );
+    }
+
+    #[test]
+    fn test_registers() {
+        let mut e = Sketch::<10, 5>::default();
+        e.insert_all((0..100usize).map(Element::from_hasher_default::<WyHash>));
+        assert_eq!(e.registers().count(), 0);
+
+        e.insert_all((0..2_000usize).map(Element::from_hasher_default::<WyHash>));
+        let Sketch::Hll(hll) = &e else {
+            panic!("expected hll representation");
+        };
+        assert_eq!(e.registers().count(), 1 << 10);
+        for (idx, rank) in e.registers().enumerate() {
+            assert_eq!(u32::from(rank), hll.get_register(idx as u32));
+        }
+    }
+
+    #[test]
+    fn test_register_histogram() {
+        let mut e = Sketch::<10, 5>::default();
+        e.insert_all((0..100usize).map(Element::from_hasher_default::<WyHash>));
+        assert_eq!(e.register_histogram(), None);
+
+        e.insert_all((0..2_000usize).map(Element::from_hasher_default::<WyHash>));
+        let histogram = e.register_histogram().unwrap();
+        assert_eq!(histogram.len(), 1 << 5);
+        assert_eq!(histogram.iter().sum::<u32>(), 1 << 10);
+        let Sketch::Hll(hll) = &e else {
+            panic!("expected hll representation");
+        };
+        assert_eq!(histogram[..], hll.histogram[..]);
+    }
+
+    #[test_case(0 => 0; "empty")]
+    #[test_case(2 => 2; "small")]
+    #[test_case(100 => 100; "array")]
+    fn test_estimate_mle_exact(n: usize) -> usize {
+        let e: Sketch = (0..n).map(Element::from_hasher_default::<WyHash>).collect();
+        e.estimate_mle()
+    }
+
+    #[test_case(1_000; "low range")]
+    #[test_case(1_000_000; "one million")]
+    fn test_estimate_mle(n: usize) {
+        let e: Sketch = (0..n).map(Element::from_hasher_default::<WyHash>).collect();
+        for estimate in [e.estimate(), e.estimate_mle()] {
+            let relative_error = estimate.abs_diff(n) as f64 / n as f64;
+            assert!(relative_error < 0.02, "{estimate} not within 2% of {n}");
+        }
+    }
+
+    #[test]
+    fn test_estimate_near_saturation() {
+        let mut e = Sketch::<12, 4>::default();
+        let (mut previous, mut previous_bound) = (0, 0.0);
+        for chunk in 0..100 {
+            e.insert_all(
+                (chunk * 100_000usize..(chunk + 1) * 100_000)
+                    .map(Element::from_hasher_default::<WyHash>),
+            );
+            let (estimate, bound) = e.estimate_with_error_bound();
+            assert!(
+                estimate as f64 >= previous as f64 - previous_bound,
+                "estimate dropped from {previous} to {estimate}"
+            );
+            (previous, previous_bound) = (estimate, bound);
+        }
+    }
+
+    #[test_case(0, 0; "empty")]
+    #[test_case(0, 2; "small into empty")]
+    #[test_case(2, 100; "array into small")]
+    #[test_case(100, 100; "array into array")]
+    #[test_case(100, 10_000; "hll into array")]
+    #[test_case(10_000, 2; "small into hll")]
+    #[test_case(10_000, 10_000; "hll into hll")]
+    fn test_merge_widening(lhs_n: usize, rhs_n: usize) {
+        let lhs_range = 0..lhs_n;
+        let rhs_range = lhs_n / 2..lhs_n / 2 + rhs_n;
+        let mut lhs: Sketch<12, 6> = lhs_range
+            .clone()
+            .map(Element::from_hasher_default::<WyHash>)
+            .collect();
+        let rhs: Sketch<12, 5> = rhs_range
+            .clone()
+            .map(Element::from_hasher_default::<WyHash>)
+            .collect();
+        lhs.merge_widening(&rhs);
+
+        // without saturated ranks, the result matches inserting everything at `W = 6`
+        let expected: Sketch<12, 6> = lhs_range
+            .chain(rhs_range)
+            .map(Element::from_hasher_default::<WyHash>)
+            .collect();
+        assert_eq!(lhs, expected);
+
+        let n = (lhs_n / 2 + rhs_n).max(lhs_n) as f64;
+        assert!((lhs.estimate() as f64 - n).abs() <= n * 0.05);
+    }
+
+    #[test_case(0; "small empty")]
+    #[test_case(2; "small")]
+    #[test_case(100; "array")]
+    #[test_case(10_000; "hll")]
+    fn test_hash_matches_eq(n: usize) {
+        let lhs: Sketch<12, 6> = (0..n).map(Element::from_hasher_default::<WyHash>).collect();
+        // built differently: split in two and merged
+        let mut rhs: Sketch<12, 6> = (0..n / 2)
+            .map(Element::from_hasher_default::<WyHash>)
+            .collect();
+        rhs.merge(
+            &(n / 2..n)
+                .map(Element::from_hasher_default::<WyHash>)
+                .collect(),
+        );
+        assert_eq!(lhs, rhs);
+
+        let mut set = std::collections::HashSet::new();
+        set.insert(lhs);
+        set.insert(rhs);
+        assert_eq!(set.len(), 1);
+
+        set.insert(
+            (n..2 * n + 1)
+                .map(Element::from_hasher_default::<WyHash>)
+                .collect(),
+        );
+        assert_eq!(set.len(), 2);
+    }
+
+    #[test]
+    fn test_try_insert() {
+        let elements = || (0..1000usize).map(Element::from_hasher_default::<WyHash>);
+
+        let header = size_of::<Sketch<12, 6>>();
+
+        // tiny budget: refuses the 129th distinct element, and stays an Array
+        let mut sketch = Sketch::<12, 6>::default();
+        let results: Vec<_> = elements().map(|e| sketch.try_insert(e, 512)).collect();
+        assert!(results[..128].iter().all(Result::is_ok));
+        let err = CapacityError {
+            required: header + 129 * 4,
+            budget: 512,
+        };
+        assert!(results[128..].iter().all(|r| r == &Err(err)));
+        assert_eq!(sketch.representation_kind(), RepresentationKind::Array);
+        assert_eq!(sketch.estimate(), 128);
+        // elements already in the sketch are still accepted
+        assert_eq!(
+            sketch.try_insert(Element::from_hasher_default::<WyHash>(0usize), 512),
+            Ok(())
+        );
+
+        // small budget: grows Sparse while its hashes fit, but refuses Hll
+        let mut sketch = Sketch::<12, 6>::default();
+        let results: Vec<_> = elements().map(|e| sketch.try_insert(e, 1024)).collect();
+        let fitting = (1024 - header) / 4;
+        assert!(results[..fitting].iter().all(Result::is_ok));
+        let err = CapacityError {
+            required: header + (fitting + 1) * 4,
+            budget: 1024,
+        };
+        assert!(results[fitting..].iter().all(|r| r == &Err(err)));
+        assert_eq!(sketch.representation_kind(), RepresentationKind::Sparse);
+        assert_eq!(sketch.estimate(), fitting);
+
+        // generous budget: promotes as usual
+        let mut sketch = Sketch::<12, 6>::default();
+        let budget = Sketch::<12, 6>::hll_size_of();
+        assert!(elements().all(|e| sketch.try_insert(e, budget).is_ok()));
+        assert_eq!(sketch, elements().collect());
+        assert_eq!(sketch.representation_kind(), RepresentationKind::Hll);
+    }
+
+    #[test_case(0; "empty")]
+    #[test_case(1; "small")]
+    #[test_case(2; "small full")]
+    #[test_case(5; "array")]
+    #[test_case(128; "array full")]
+    #[test_case(129; "sparse")]
+    #[test_case(771; "sparse full")]
+    fn test_iter_sparse(n: usize) {
+        let elements: Vec<Element> = (0..n).map(Element::from_hasher_default::<WyHash>).collect();
+        let sketch: Sketch = elements.iter().collect();
+        let mut encoded: Vec<u32> = sketch.iter_sparse().unwrap().collect();
+        encoded.sort();
+        let mut expected: Vec<u32> = elements.iter().map(|e| e.0).collect();
+        expected.sort();
+        assert_eq!(encoded, expected);
+
+        let rebuilt = Sketch::from_encoded_hashes(sketch.iter_sparse().unwrap());
+        assert_eq!(rebuilt, sketch);
+    }
+
+    #[test]
+    fn test_iter_sparse_hll() {
+        let sketch: Sketch = (0..772usize)
+            .map(Element::from_hasher_default::<WyHash>)
+            .collect();
+        assert!(sketch.iter_sparse().is_none());
+    }
+
+    #[test_case(&[]; "none")]
+    #[test_case(&[0, 1, 2]; "small")]
+    #[test_case(&[2, 50, 1, 30]; "array")]
+    #[test_case(&[2, 50, 1000, 30, 0, 500]; "hll")]
+    fn test_merge_all(sizes: &[usize]) {
+        let sketches: Vec<Sketch> = sizes
+            .iter()
+            .enumerate()
+            .map(|(i, &n)| {
+                (i * 10..i * 10 + n)
+                    .map(Element::from_hasher_default::<WyHash>)
+                    .collect()
+            })
+            .collect();
+        let mut sequential = Sketch::default();
+        for sketch in &sketches {
+            sequential.merge(sketch);
+        }
+
+        let merged = Sketch::merge_all(&sketches);
+        let sorted_sparse = |sketch: &Sketch| {
+            let mut items: Vec<u32> = sketch.iter_sparse()?.collect();
+            items.sort();
+            Some(items)
+        };
+        assert_eq!(sorted_sparse(&merged), sorted_sparse(&sequential));
+        assert_eq!(
+            merged.representation_kind(),
+            sequential.representation_kind()
+        );
+        if let Sketch::Hll(_) = merged {
+            assert_eq!(merged, sequential);
+        }
+        assert_eq!(merged.estimate(), sequential.estimate());
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test_case(1000, 1; "small")]
+    #[test_case(50, 2; "array")]
+    #[test_case(1000, 100; "hll")]
+    fn test_par_merge_all(count: usize, per_sketch: usize) {
+        let sketches: Vec<Sketch> = (0..count)
+            .map(|i| {
+                (i * per_sketch / 2..i * per_sketch / 2 + per_sketch)
+                    .map(Element::from_hasher_default::<WyHash>)
+                    .collect()
+            })
+            .collect();
+        let mut sequential = Sketch::default();
+        for sketch in &sketches {
+            sequential.merge(sketch);
+        }
+
+        let parallel = Sketch::par_merge_all(&sketches);
+        // sparse items may be stored in a different order
+        let sorted_sparse = |sketch: &Sketch| {
+            let mut items: Vec<u32> = sketch.iter_sparse()?.collect();
+            items.sort();
+            Some(items)
+        };
+        if let Some(items) = sorted_sparse(&sequential) {
+            assert_eq!(sorted_sparse(&parallel), Some(items));
+            assert_eq!(parallel.estimate(), sequential.estimate());
+        } else {
+            assert_eq!(parallel, sequential);
+            assert_eq!(parallel.estimate(), sequential.estimate());
+        }
+    }
+
+    #[test_case(0; "empty")]
+    #[test_case(2; "small")]
+    #[test_case(100; "array")]
+    #[test_case(500; "sparse")]
+    #[test_case(771; "sparse full")]
+    #[test_case(1_000; "hll")]
+    fn test_reserve_dense(before: usize) {
+        let elements = || (0..10_000usize).map(Element::from_hasher_default::<WyHash>);
+        let mut lazy = Sketch::<12, 6>::default();
+        lazy.insert_all(elements().take(before));
+        let mut dense = lazy.clone();
+        dense.reserve_dense();
+        assert_eq!(dense.representation_kind(), RepresentationKind::Hll);
+        assert_eq!(dense.estimate(), lazy.estimate());
+        assert_eq!(dense.estimate_f64(), lazy.estimate_f64());
+
+        lazy.insert_all(elements().skip(before));
+        dense.insert_all(elements().skip(before));
+        assert_eq!(dense, lazy);
+        assert_eq!(dense.estimate(), lazy.estimate());
+    }
+
+    #[test]
+    fn test_heap_size() {
+        assert_eq!(Sketch::<12, 6>::default().heap_size(), 8);
+
+        let mut sketch = Sketch::<12, 6>::default();
+        for i in 0..128usize {
+            sketch.insert(Element::from_hasher_default::<WyHash>(i));
+            if let Sketch::Array(arr) = &sketch {
+                // items may be stored inline with the `smallvec` feature
+                let header = if cfg!(feature = "smallvec") { 16 } else { 32 };
+                assert!(sketch.heap_size() >= header + arr.len() * 4);
+            }
+        }
+
+        let hll: Sketch<12, 6> = (0..1000usize)
+            .map(Element::from_hasher_default::<WyHash>)
+            .collect();
+        // with the `theta` feature, the sample of 1000 hashes in a 1024-element
+        // allocation, and its `Vec` header
+        let theta = if cfg!(feature = "theta") {
+            4096 + 24
+        } else {
+            0
+        };
+        // with the `minhash` feature, 128 bins behind a `Box`
+        let theta = theta
+            + if cfg!(feature = "minhash") {
+                512 + 8
+            } else {
+                0
+            };
+        // 771 register words, the `Vec` header, and 64 histogram counts
+        // behind a `Box`
+        let header = 256 + 16;
+        assert_eq!(hll.heap_size(), 3108 + theta + header);
+        let mut hll: Sketch<14, 6> = (0..1000usize)
+            .map(Element::from_hasher_default::<WyHash>)
+            .collect();
+        hll.reserve_dense();
+        assert_eq!(hll.heap_size(), 12324 + theta + header);
+    }
+
+    #[test]
+    fn test_compact() {
+        let mut sketch = Sketch::<12, 6>::Hll(HyperLogLog::new(&[]));
+        sketch.compact();
+        assert_eq!(sketch, Sketch::default());
+        assert_eq!(sketch.representation_kind(), RepresentationKind::Small);
+
+        // anything non-empty is kept as-is
+        for n in [1, 2, 100, 10_000] {
+            let mut sketch = Sketch::<12, 6>::Hll(HyperLogLog::new(&[]));
+            sketch.insert_all((0..n).map(Element::from_hasher_default::<WyHash>));
+            let expected = sketch.clone();
+            sketch.compact();
+            assert_eq!(sketch, expected);
+        }
+        let mut sketch: Sketch<12, 6> = (0..100usize)
+            .map(Element::from_hasher_default::<WyHash>)
+            .collect();
+        let expected = sketch.clone();
+        sketch.compact();
+        assert_eq!(sketch, expected);
+    }
+
+    #[test]
+    fn test_representation_kind() {
+        let mut sketch = Sketch::<12, 6>::default();
+        assert_eq!(sketch.representation_kind(), RepresentationKind::Small);
+        for i in 1..=1000usize {
+            sketch.insert(Element::from_hasher_default::<WyHash>(i));
+            let expected = match i {
+                ..=2 => RepresentationKind::Small,
+                3..=128 => RepresentationKind::Array,
+                129..=771 => RepresentationKind::Sparse,
+                _ => RepresentationKind::Hll,
+            };
+            assert_eq!(sketch.representation_kind(), expected, "after {i} inserts");
+        }
+        sketch.clear();
+        assert_eq!(sketch.representation_kind(), RepresentationKind::Small);
+    }
+
+    #[test_case(Sketch::<10, 6>::default(); "p10")]
+    #[test_case(Sketch::<12, 6>::default(); "p12")]
+    #[test_case(Sketch::<13, 5>::default(); "p13 w5")]
+    fn test_sparse_crossover<const P: usize, const W: usize>(mut sketch: Sketch<P, W>) {
+        let max_len = Sparse::<P, W>::MAX_LEN;
+        let mut dense = Sketch::<P, W>::default();
+        dense.reserve_dense();
+        for i in 0..=max_len {
+            let upgrade =
+                sketch.insert_returning_upgrade(Element::from_hasher_default::<WyHash>(i));
+            if i < ARRAY_MAX_CAPACITY {
+                continue;
+            }
+            if i < max_len {
+                // exact, and never larger than the dense registers
+                assert_eq!(sketch.representation_kind(), RepresentationKind::Sparse);
+                assert_eq!(sketch.estimate(), i + 1);
+                assert!(sketch.heap_size() <= dense.heap_size(), "after {i} inserts");
+            } else {
+                assert_eq!(upgrade, Some(RepresentationKind::Hll));
+                assert!(sketch.heap_size() >= dense.heap_size());
+            }
+        }
+        let expected: Sketch<P, W> = (0..=max_len)
+            .map(Element::from_hasher_default::<WyHash>)
+            .collect();
+        assert_eq!(sketch, expected);
+        assert!(sketch.estimate() > max_len);
+    }
+
+    #[test]
+    fn test_sparse_disabled_for_small_precision() {
+        let mut sketch = Sketch::<9, 6>::default();
+        let upgrades: Vec<_> = (0..200usize)
+            .filter_map(|i| {
+                sketch.insert_returning_upgrade(Element::from_hasher_default::<WyHash>(i))
+            })
+            .collect();
+        assert_eq!(
+            upgrades,
+            [RepresentationKind::Array, RepresentationKind::Hll]
+        );
+    }
+
+    #[test]
+    fn test_insert_returning_upgrade() {
+        let mut sketch = Sketch::<12, 6>::default();
+        let upgrades: Vec<(usize, RepresentationKind)> = (1..=1000usize)
+            .filter_map(|i| {
+                let element = Element::from_hasher_default::<WyHash>(i);
+                sketch
+                    .insert_returning_upgrade(element)
+                    .map(|kind| (i, kind))
+            })
+            .collect();
+        assert_eq!(
+            upgrades,
+            [
+                (3, RepresentationKind::Array),
+                (129, RepresentationKind::Sparse),
+                (772, RepresentationKind::Hll)
+            ]
+        );
+
+        // repeated elements never upgrade
+        let mut sketch = Sketch::<12, 6>::default();
+        for _ in 0..10 {
+            let element = Element::from_hasher_default::<WyHash>(1);
+            assert_eq!(sketch.insert_returning_upgrade(element), None);
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn test_tracing_upgrade_events() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+        use tracing::span::{Attributes, Id, Record};
+        use tracing::{Event, Metadata};
+
+        struct CountEvents(Arc<AtomicUsize>);
+
+        impl tracing::Subscriber for CountEvents {
+            fn enabled(&self, _: &Metadata<'_>) -> bool {
+                true
+            }
+            fn new_span(&self, _: &Attributes<'_>) -> Id {
+                Id::from_u64(1)
+            }
+            fn record(&self, _: &Id, _: &Record<'_>) {}
+            fn record_follows_from(&self, _: &Id, _: &Id) {}
+            fn event(&self, _: &Event<'_>) {
+                self.0.fetch_add(1, Ordering::Relaxed);
+            }
+            fn enter(&self, _: &Id) {}
+            fn exit(&self, _: &Id) {}
+        }
+
+        let events = Arc::new(AtomicUsize::new(0));
+        tracing::subscriber::with_default(CountEvents(events.clone()), || {
+            let mut sketch = Sketch::<12, 6>::default();
+            for i in 0..1000usize {
+                sketch.insert(Element::from_hasher_default::<WyHash>(i));
+            }
+            assert_eq!(sketch.representation_kind(), RepresentationKind::Hll);
+        });
+        // Small to Array, Array to Sparse, and Sparse to Hll
+        assert_eq!(events.load(Ordering::Relaxed), 3);
+    }
+
+    #[test_case(1_000, 1_000; "same size")]
+    #[test_case(500, 20_000; "different sizes")]
+    fn test_merge_max_rank_only_hll(lhs_n: usize, rhs_n: usize) {
+        let lhs: Sketch<12, 6> = (0..lhs_n)
+            .map(Element::from_hasher_default::<WyHash>)
+            .collect();
+        let rhs: Sketch<12, 6> = (lhs_n / 2..lhs_n / 2 + rhs_n)
+            .map(Element::from_hasher_default::<WyHash>)
+            .collect();
+
+        let mut merged = lhs.clone();
+        merged.merge(&rhs);
+        let mut max_rank = lhs.clone();
+        max_rank.merge_max_rank_only(&rhs);
+        assert_eq!(max_rank, merged);
+        assert_eq!(max_rank.estimate(), merged.estimate());
+    }
+
+    #[test_case(0, 0; "empty")]
+    #[test_case(2, 100; "small and array")]
+    #[test_case(100, 1_000; "array and hll")]
+    fn test_merge_max_rank_only_sparse(lhs_n: usize, rhs_n: usize) {
+        let lhs: Sketch<12, 6> = (0..lhs_n)
+            .map(Element::from_hasher_default::<WyHash>)
+            .collect();
+        let rhs: Sketch<12, 6> = (lhs_n..lhs_n + rhs_n)
+            .map(Element::from_hasher_default::<WyHash>)
+            .collect();
+
+        let mut max_rank = lhs.clone();
+        max_rank.merge_max_rank_only(&rhs);
+        assert_eq!(max_rank.representation_kind(), RepresentationKind::Hll);
+        let (mut lhs, mut rhs) = (lhs, rhs);
+        lhs.reserve_dense();
+        rhs.reserve_dense();
+        let expected: Vec<u8> = (lhs.registers().zip(rhs.registers()))
+            .map(|(l, r)| l.max(r))
+            .collect();
+        assert_eq!(max_rank.registers().collect::<Vec<_>>(), expected);
+    }
+
+    #[test_case(0, 0; "empty")]
+    #[test_case(50, 25; "array")]
+    #[test_case(5_000, 2_500; "hll")]
+    fn test_merge_and_compare(lhs_n: usize, overlap: usize) {
+        // lhs has 0..lhs_n, rhs has overlap items of it plus as many new ones
+        let mut lhs: Sketch<12, 6> = (0..lhs_n)
+            .map(Element::from_hasher_default::<WyHash>)
+            .collect();
+        let rhs: Sketch<12, 6> = (lhs_n - overlap..lhs_n + overlap)
+            .map(Element::from_hasher_default::<WyHash>)
+            .collect();
+        let true_union = lhs_n + overlap;
+
+        let error = lhs.merge_and_compare(&rhs, true_union);
+        let expected = lhs.estimate().abs_diff(true_union) as f64 / true_union.max(1) as f64;
+        assert_eq!(error, expected);
+        assert!(error < 3.0 * Sketch::<12, 6>::standard_error());
+        if true_union <= 128 {
+            assert_eq!(error, 0.0);
+        }
+    }
+
+    #[test]
+    fn test_merge_and_compare_empty_union() {
+        let mut lhs = Sketch::<12, 6>::default();
+        let rhs: Sketch<12, 6> = [Element::from_hasher_default::<WyHash>(1)]
+            .into_iter()
+            .collect();
+        assert_eq!(lhs.merge_and_compare(&rhs, 0), f64::INFINITY);
+    }
+
+    #[test_case(0, 1 => RepresentationKind::Small; "small into small")]
+    #[test_case(1, 100 => RepresentationKind::Array; "array into small")]
+    #[test_case(1, 10_000 => RepresentationKind::Hll; "hll into small")]
+    #[test_case(10_000, 1 => RepresentationKind::Hll; "small into hll")]
+    fn test_merge_reporting(lhs_n: usize, rhs_n: usize) -> RepresentationKind {
+        let mut lhs: Sketch<12, 6> = (0..lhs_n)
+            .map(Element::from_hasher_default::<WyHash>)
+            .collect();
+        let rhs: Sketch<12, 6> = (lhs_n..lhs_n + rhs_n)
+            .map(Element::from_hasher_default::<WyHash>)
+            .collect();
+        lhs.merge_reporting(&rhs)
+    }
+
+    #[test]
+    fn test_standard_error() {
+        assert!((Sketch::<12, 6>::standard_error() - 0.0163).abs() < 0.0001);
+        assert!((Sketch::<4, 4>::standard_error() - 0.26).abs() < 0.0001);
+        assert!((Sketch::<18, 6>::standard_error() - 0.00203).abs() < 0.00001);
+    }
+
+    #[test]
+    fn test_max_reliable_cardinality() {
+        let w4 = Sketch::<14, 4>::max_reliable_cardinality();
+        let w5 = Sketch::<14, 5>::max_reliable_cardinality();
+        let w6 = Sketch::<14, 6>::max_reliable_cardinality();
+        assert_eq!(w4, 2_684_354);
+        assert!(w4 < w5 && w5 < w6);
+        assert!(w5 > 2_000_000_000);
+    }
+
+    const _: usize = Sketch::<12, 6>::dense_hll_bytes();
+
+    #[test_case(Sketch::<4, 4>::dense_hll_bytes(), 20; "p4_w4")]
+    #[test_case(Sketch::<12, 6>::dense_hll_bytes(), 3084; "p12_w6")]
+    #[test_case(Sketch::<18, 6>::dense_hll_bytes(), 196620; "p18_w6")]
+    fn test_dense_hll_bytes(size: usize, expected: usize) {
+        assert_eq!(size, expected);
+    }
+
+    #[test]
+    fn test_dense_hll_bytes_matches_allocation() {
+        let sketch: Sketch<12, 6> = (0..10_000usize)
+            .map(Element::from_hasher_default::<WyHash>)
+            .collect();
+        let Sketch::Hll(hll) = &sketch else {
+            panic!("expected the Hll representation");
+        };
+        assert_eq!(
+            hll.registers.capacity() * size_of::<u32>(),
+            Sketch::<12, 6>::dense_hll_bytes()
+        );
+    }
+
+    #[test_case(Sketch::<4, 4>::hll_size_of(), 124; "p4_w4")]
+    #[test_case(Sketch::<12, 6>::hll_size_of(), 3380; "p12_w6")]
+    #[test_case(Sketch::<18, 6>::hll_size_of(), 196916; "p18_w6")]
+    fn test_hll_size_of(size: usize, expected: usize) {
+        // the inline `smallvec` storage of the Array representation, or the
+        // `theta` sample and `minhash` bins of the Hll representation, enlarge
+        // `Sketch`
+        let enlarged = size_of::<Sketch>() - 40;
+        let theta = if cfg!(feature = "theta") { 4096 } else { 0 };
+        let minhash = if cfg!(feature = "minhash") { 512 } else { 0 };
+        assert_eq!(size, expected + enlarged + theta + minhash);
+    }
+
+    #[test_case(0; "empty")]
+    #[test_case(2; "small")]
+    #[test_case(100; "array")]
+    #[test_case(10_000; "hll")]
+    fn test_from_hashes(n: usize) {
+        let build_hasher = core::hash::BuildHasherDefault::<WyHash>::default();
+        let hashes: Vec<u64> = (0..n).map(|i| build_hasher.hash_one(i)).collect();
+
+        let from_hashes = Sketch::<12, 6>::from_hashes(hashes.iter().copied());
+        let inserted: Sketch<12, 6> = (0..n).map(Element::from_hasher_default::<WyHash>).collect();
+        assert_eq!(from_hashes, inserted);
+
+        let encoded = hashes.iter().map(|&h| Element::<12, 6>::from_hashed(h).0);
+        let from_encoded = Sketch::<12, 6>::from_encoded_hashes(encoded.chain([0]));
+        assert_eq!(from_encoded, inserted);
+        assert_eq!(from_encoded.estimate(), inserted.estimate());
+    }
+
     #[test]
     fn test_insert() {
         // Create a new Sketch.
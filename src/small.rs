@@ -6,7 +6,8 @@
 //! - 2..33 bits    - store 31-bit encoded hash
 //! - 34..63 bits   - store 31-bit encoded hash
 
-use std::fmt::{Debug, Formatter};
+use core::fmt::{Debug, Formatter};
+use core::hash::{Hash, Hasher};
 
 use crate::array::Array;
 use crate::sketch::{Sketch, SketchTrait};
@@ -17,11 +18,19 @@ use serde::{Deserialize, Serialize};
 const SMALL_MASK: u64 = 0x0000_0000_7fff_ffff;
 
 /// Small representation container
-#[derive(PartialEq, Clone, Default)]
+#[derive(Clone, Default)]
 #[cfg_attr(feature = "with_serde", derive(Serialize, Deserialize))]
 pub(crate) struct Small<const P: usize, const W: usize>(u64);
 
 impl<const P: usize, const W: usize> Small<P, W> {
+    /// Move into a `Small` with other const parameters
+    ///
+    /// Caller is responsible for ensuring `(P2, W2) == (P, W)`
+    #[inline]
+    pub(crate) fn cast<const P2: usize, const W2: usize>(self) -> Small<P2, W2> {
+        Small(self.0)
+    }
+
     /// Insert encoded hash into `Small` representation.
     /// Returns true on success, false otherwise.
     #[inline]
@@ -57,11 +66,47 @@ impl<const P: usize, const W: usize> Small<P, W> {
         ((self.0 >> 33) & SMALL_MASK) as u32
     }
 
+    /// Check that `data` is a word this representation could have produced
+    ///
+    /// The type bits must be `00`, a 2-nd hash implies a 1-st one, the two
+    /// hashes must differ, and every stored hash must have a non-zero rank.
+    #[inline]
+    pub(crate) fn is_well_formed(&self) -> bool {
+        let rank = |h: u32| h & ((1 << W) - 1);
+        let (h1, h2) = (self.h1(), self.h2());
+        self.0 & 0b11 == 0
+            && (h1 == 0 || rank(h1) != 0)
+            && (h2 == 0 || (h1 != 0 && h1 != h2 && rank(h2) != 0))
+    }
+
     /// Return items stored within `Small` representation
     #[inline]
     pub(crate) fn items(&self) -> [u32; 2] {
         [self.h1(), self.h2()]
     }
+
+    /// Return stored items in ascending order, independent of insertion order
+    #[inline]
+    fn sorted_items(&self) -> [u32; 2] {
+        let [h1, h2] = self.items();
+        [h1.min(h2), h1.max(h2)]
+    }
+}
+
+impl<const P: usize, const W: usize> PartialEq for Small<P, W> {
+    /// Compare the stored items as sets, whatever order they were inserted in
+    fn eq(&self, other: &Self) -> bool {
+        self.sorted_items() == other.sorted_items()
+    }
+}
+
+impl<const P: usize, const W: usize> Eq for Small<P, W> {}
+
+impl<const P: usize, const W: usize> Hash for Small<P, W> {
+    /// Hash the sorted stored items, consistent with `PartialEq`
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.sorted_items().hash(state);
+    }
 }
 
 impl<const P: usize, const W: usize> SketchTrait<P, W> for Small<P, W> {
@@ -72,6 +117,13 @@ impl<const P: usize, const W: usize> SketchTrait<P, W> for Small<P, W> {
         } else {
             // upgrade from `Small` to `Array` representation
             let arr = Array::<P, W>::from_small(self.h1(), self.h2(), h);
+            #[cfg(feature = "tracing")]
+            tracing::debug!(
+                p = P,
+                w = W,
+                cardinality = 3,
+                "sketch upgraded from Small to Array"
+            );
             Some(Sketch::Array(arr))
         }
     }
@@ -86,14 +138,25 @@ impl<const P: usize, const W: usize> SketchTrait<P, W> for Small<P, W> {
         }
     }
 
+    /// Check if `Small` representation has no items
+    #[inline]
+    fn is_empty_sketch(&self) -> bool {
+        self.h1() == 0 && self.h2() == 0
+    }
+
     /// Return memory size of `Small` representation
     fn size_of(&self) -> usize {
-        std::mem::size_of::<Self>()
+        core::mem::size_of::<Self>()
+    }
+
+    /// Return memory size of `Small` representation, which has no heap storage
+    fn heap_size(&self) -> usize {
+        self.size_of()
     }
 }
 
 impl<const P: usize, const W: usize> Debug for Small<P, W> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         f.write_str(&self.to_string())
     }
 }
@@ -111,6 +174,6 @@ mod tests {
 
     #[test]
     fn small_size() {
-        assert_eq!(std::mem::size_of::<Small<0, 0>>(), 8);
+        assert_eq!(core::mem::size_of::<Small<0, 0>>(), 8);
     }
 }
@@ -0,0 +1,290 @@
+//! ## Sparse representation
+//! Allows to count medium cardinality exactly, from above the `Array`
+//! capacity until the dense HyperLogLog registers would take less memory, or
+//! until `MAX_EXACT_LEN` elements, whichever comes first.
+//!
+//! Like the sparse encoding of HyperLogLog++, each encoded hash keeps its
+//! element's register index and rank, so converting to dense registers loses
+//! nothing. They're kept sorted for binary search, behind a short unsorted
+//! tail of recent inserts that is merged in every `TAIL_CAPACITY` inserts, so
+//! that an insert doesn't shift the whole sorted list.
+
+use alloc::vec::Vec;
+use core::fmt::{Debug, Formatter};
+use core::hash::{Hash, Hasher};
+use core::mem::{size_of, size_of_val};
+use core::ops::Deref;
+
+use crate::array::MAX_CAPACITY as ARRAY_MAX_CAPACITY;
+use crate::hyperloglog::HyperLogLog;
+use crate::sketch::{Sketch, SketchTrait};
+
+/// Maximum number of unsorted encoded hashes inserted after the sorted ones
+const TAIL_CAPACITY: usize = 64;
+
+/// Maximum number of elements counted exactly by the sparse representation,
+/// whatever the registers' size
+///
+/// Encoded hashes keep `31 - W` bits of the hash, so `n` elements have about
+/// `n^2 / 2^(32 - W)` chance of two sharing an encoded hash and being counted
+/// once: 1.6% for `W = 6` here, but already 36 collisions expected for the
+/// 49155 elements the registers' size would allow at `P = 18`.
+pub(crate) const MAX_EXACT_LEN: usize = 1024;
+
+/// Sparse representation container
+///
+/// Distinct encoded hashes, sorted up to `sorted`, then unsorted.
+#[derive(Clone)]
+pub(crate) struct Sparse<const P: usize, const W: usize> {
+    items: Vec<u32>,
+    sorted: usize,
+}
+
+impl<const P: usize, const W: usize> Sparse<P, W> {
+    /// Maximum number of elements stored in sparse representation: as many
+    /// `u32` words as the Hll representation's registers, up to
+    /// `MAX_EXACT_LEN`
+    pub(crate) const MAX_LEN: usize = if HyperLogLog::<P, W>::HLL_SLICE_LEN < MAX_EXACT_LEN {
+        HyperLogLog::<P, W>::HLL_SLICE_LEN
+    } else {
+        MAX_EXACT_LEN
+    };
+
+    /// Whether the Array representation upgrades to this one, which only
+    /// holds more elements for large enough `P`
+    pub(crate) const ENABLED: bool = Self::MAX_LEN > ARRAY_MAX_CAPACITY;
+
+    /// Move into a `Sparse` with other const parameters
+    ///
+    /// Caller is responsible for ensuring `(P2, W2) == (P, W)`
+    #[inline]
+    pub(crate) fn cast<const P2: usize, const W2: usize>(self) -> Sparse<P2, W2> {
+        Sparse {
+            items: self.items,
+            sorted: self.sorted,
+        }
+    }
+
+    /// Create new instance of `Sparse` representation from the items of a
+    /// full `Array` representation and a new item `h`
+    #[inline]
+    pub(crate) fn from_array(items: &[u32], h: u32) -> Self {
+        let mut sorted = Vec::with_capacity(items.len() + items.len() / 2);
+        sorted.extend_from_slice(items);
+        sorted.push(h);
+        sorted.sort_unstable();
+        Self::from_sorted(sorted)
+    }
+
+    /// Create a new instance from distinct items in ascending order
+    ///
+    /// Caller is responsible for ensuring the vec length is <= MAX_LEN
+    #[inline]
+    pub(crate) fn from_sorted(items: Vec<u32>) -> Self {
+        Self {
+            sorted: items.len(),
+            items,
+        }
+    }
+
+    /// Check whether encoded hash `h` is stored
+    #[inline]
+    pub(crate) fn contains(&self, h: u32) -> bool {
+        let (sorted, tail) = self.items.split_at(self.sorted);
+        sorted.binary_search(&h).is_ok() || tail.contains(&h)
+    }
+
+    /// Insert encoded hash into `Sparse` representation
+    /// Returns true on success, false if it's full.
+    #[inline]
+    pub(crate) fn insert(&mut self, h: u32) -> bool {
+        if self.contains(h) {
+            return true;
+        }
+        let len = self.items.len();
+        if len == Self::MAX_LEN {
+            return false;
+        }
+        // grow by half, but never past the Hll registers' footprint
+        if len == self.items.capacity() {
+            self.items
+                .reserve_exact((len / 2).clamp(1, Self::MAX_LEN - len));
+        }
+        self.items.push(h);
+        if self.items.len() - self.sorted == TAIL_CAPACITY {
+            self.merge_tail();
+        }
+        true
+    }
+
+    /// Sort the tail and merge it into the sorted items
+    fn merge_tail(&mut self) {
+        let tail_len = self.items.len() - self.sorted;
+        let mut tail = [0; TAIL_CAPACITY];
+        let tail = &mut tail[..tail_len];
+        tail.copy_from_slice(&self.items[self.sorted..]);
+        tail.sort_unstable();
+
+        // merge from the back, into the slots freed by copying the tail out
+        let (mut i, mut j) = (self.sorted, tail_len);
+        while j > 0 {
+            if i > 0 && self.items[i - 1] > tail[j - 1] {
+                self.items[i + j - 1] = self.items[i - 1];
+                i -= 1;
+            } else {
+                self.items[i + j - 1] = tail[j - 1];
+                j -= 1;
+            }
+        }
+        self.sorted = self.items.len();
+    }
+
+    /// All items in ascending order
+    pub(crate) fn to_sorted_vec(&self) -> Vec<u32> {
+        let mut items = self.items.clone();
+        if self.sorted < items.len() {
+            items.sort_unstable();
+        }
+        items
+    }
+
+    /// Merge the unsorted tail and release any spare capacity
+    #[inline]
+    pub(crate) fn shrink_to_fit(&mut self) {
+        if self.sorted < self.items.len() {
+            self.merge_tail();
+        }
+        self.items.shrink_to_fit();
+    }
+}
+
+impl<const P: usize, const W: usize> SketchTrait<P, W> for Sparse<P, W> {
+    /// Insert encoded hash into `Sparse` representation.
+    #[inline]
+    fn insert_encoded_hash(&mut self, h: u32) -> Option<Sketch<P, W>> {
+        if self.insert(h) {
+            None
+        } else {
+            // upgrade from `Sparse` to `HyperLogLog` representation
+            #[cfg(feature = "tracing")]
+            tracing::debug!(
+                p = P,
+                w = W,
+                cardinality = self.estimate_sketch() + 1,
+                "sketch upgraded from Sparse to Hll"
+            );
+            Some(Sketch::Hll(HyperLogLog::promote(self, h)))
+        }
+    }
+
+    /// Return cardinality estimate of `Sparse` representation
+    #[inline]
+    fn estimate_sketch(&self) -> usize {
+        self.items.len()
+    }
+
+    /// `Sparse` representation always holds more items than `Array`
+    #[inline]
+    fn is_empty_sketch(&self) -> bool {
+        false
+    }
+
+    /// Return memory size of `Sparse` representation
+    #[inline]
+    fn size_of(&self) -> usize {
+        size_of_val(self)
+    }
+
+    /// Return memory size of `Sparse` representation, including its heap storage
+    #[inline]
+    fn heap_size(&self) -> usize {
+        size_of_val(self) + self.items.capacity() * size_of::<u32>()
+    }
+}
+
+impl<const P: usize, const W: usize> Debug for Sparse<P, W> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.write_str(&self.to_string())
+    }
+}
+
+impl<const P: usize, const W: usize> PartialEq for Sparse<P, W> {
+    /// Compare the stored items as sets, whatever order they were inserted in
+    fn eq(&self, other: &Self) -> bool {
+        self.items.len() == other.items.len() && self.to_sorted_vec() == other.to_sorted_vec()
+    }
+}
+
+impl<const P: usize, const W: usize> Eq for Sparse<P, W> {}
+
+impl<const P: usize, const W: usize> Hash for Sparse<P, W> {
+    /// Hash the sorted stored items, consistent with `PartialEq`
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.to_sorted_vec().hash(state);
+    }
+}
+
+impl<const P: usize, const W: usize> Deref for Sparse<P, W> {
+    type Target = [u32];
+
+    /// All stored items, not necessarily in order
+    fn deref(&self) -> &Self::Target {
+        &self.items
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test]
+    fn sparse_size() {
+        assert_eq!(core::mem::size_of::<Sparse<0, 0>>(), 32);
+    }
+
+    #[test]
+    fn test_enabled() {
+        // the registers of smaller sketches fit in fewer words than an array
+        assert_eq!(
+            [Sparse::<9, 6>::ENABLED, Sparse::<10, 6>::ENABLED],
+            [false, true]
+        );
+        assert_eq!(Sparse::<12, 6>::MAX_LEN, 771);
+    }
+
+    #[test]
+    fn test_max_len_capped() {
+        // collisions of encoded hashes would be routine in larger registers
+        assert_eq!(
+            [Sparse::<13, 6>::MAX_LEN, Sparse::<18, 6>::MAX_LEN],
+            [MAX_EXACT_LEN; 2]
+        );
+    }
+
+    #[test_case(129; "untouched tail")]
+    #[test_case(200; "merged tail")]
+    #[test_case(771; "full")]
+    fn test_insert_keeps_items(n: u32) {
+        // insert in a scrambled order, so tails merge in the middle
+        let items: Vec<u32> = (1..=n).map(|i| (i * 7919 % 1024) << 6 | 1).collect();
+        let mut sparse = Sparse::<12, 6>::from_array(&items[..128], items[128]);
+        for &h in &items[129..] {
+            assert!(sparse.insert(h));
+        }
+        for &h in &items {
+            assert!(sparse.contains(h));
+            assert!(sparse.insert(h));
+        }
+        assert_eq!(sparse.estimate_sketch(), n as usize);
+        assert!(sparse.heap_size() <= size_of_val(&sparse) + 771 * 4);
+
+        let mut expected = items.clone();
+        expected.sort_unstable();
+        assert_eq!(sparse.to_sorted_vec(), expected);
+        sparse.shrink_to_fit();
+        assert_eq!(*sparse, expected[..]);
+        assert!(!sparse.contains(1025 << 6 | 1));
+        assert_eq!(sparse.insert(1025 << 6 | 1), n < 771);
+    }
+}
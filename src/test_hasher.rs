@@ -0,0 +1,126 @@
+//! Deterministic hasher for reproducible tests
+//!
+//! Hashers like `WyHash` are fast and well distributed, but nothing promises
+//! that their output stays the same across versions, and `Hash` impls write
+//! integers in native byte order and width. Fixtures asserting exact estimates
+//! can then break on another platform or after a dependency update.
+use core::hash::Hasher;
+
+/// A `Hasher` with stable output across platforms, architectures and releases
+///
+/// **For testing only.** It lets downstream tests assert exact estimates and
+/// serialized bytes, but it is not designed for real estimation: use a fast,
+/// well-distributed hasher (or a `Digest`, for untrusted input) there.
+///
+/// Bytes are hashed with 64-bit FNV-1a, and the result is finished with the
+/// SplitMix64 mixer, so that even small integer inputs spread over all the
+/// bits that `Element` encodes. Integers are written as little-endian bytes,
+/// and `usize`/`isize` are always widened to 64 bits.
+///
+/// ```
+/// use cardinality_estimator_safe::{DeterministicHasher, Element, Sketch};
+///
+/// let sketch: Sketch = (0..1000usize)
+///     .map(Element::from_hasher_default::<DeterministicHasher>)
+///     .collect();
+/// assert_eq!(sketch.estimate(), 1003);
+/// ```
+#[derive(Debug, Clone)]
+pub struct DeterministicHasher(u64);
+
+impl DeterministicHasher {
+    const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const FNV_PRIME: u64 = 0x0100_0000_01b3;
+}
+
+impl Default for DeterministicHasher {
+    fn default() -> Self {
+        Self(Self::FNV_OFFSET_BASIS)
+    }
+}
+
+impl Hasher for DeterministicHasher {
+    fn finish(&self) -> u64 {
+        let mut z = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ u64::from(byte)).wrapping_mul(Self::FNV_PRIME);
+        }
+    }
+
+    // signed integers are forwarded to these by the default impls
+
+    fn write_u16(&mut self, i: u16) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u128(&mut self, i: u128) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_integer_writes_are_little_endian() {
+        let mut bytes = DeterministicHasher::default();
+        bytes.write(&[1, 0, 0, 0, 0, 0, 0, 0]);
+
+        let mut int = DeterministicHasher::default();
+        int.write_usize(1);
+        assert_eq!(int.finish(), bytes.finish());
+
+        let mut int = DeterministicHasher::default();
+        int.write_i64(1);
+        assert_eq!(int.finish(), bytes.finish());
+    }
+
+    #[cfg(feature = "with_serde")]
+    #[test]
+    fn test_serialized_sketches_are_stable() {
+        use crate::{Element, Sketch};
+        use sha2::{Digest, Sha256};
+
+        let serialize = |n: usize| {
+            let sketch: Sketch = (0..n)
+                .map(Element::from_hasher_default::<DeterministicHasher>)
+                .collect();
+            postcard::to_allocvec(&sketch).expect("serialization failed")
+        };
+        assert_eq!(
+            serialize(3),
+            [1, 3, 195, 181, 187, 180, 7, 193, 169, 227, 255, 2, 196, 179, 243, 232, 1]
+        );
+        // the registers are too long to spell out, so compare their digest;
+        // `checksum` appends a CRC to them
+        let digest: String = Sha256::digest(serialize(1000))
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+        let expected = if cfg!(feature = "checksum") {
+            "da1e0b0643722d86dff778c0ded10dff1e2688de0c574e39f128b5f611b73dfa"
+        } else {
+            "5fa1fea0b5749e573dd4012307f4026714af3aa0959084c141cd7f81957aef5c"
+        };
+        assert_eq!(digest, expected);
+    }
+}
@@ -0,0 +1,136 @@
+//! ## K-minimum-values sample
+//! With the `theta` feature, the HyperLogLog representation also keeps the
+//! `THETA_K` smallest encoded hashes it has seen. The sample is complete below
+//! its largest value, so two samples can be intersected like Theta sketches,
+//! which is much more accurate for small overlaps than the inclusion-exclusion
+//! that plain HyperLogLog registers allow.
+
+use alloc::vec::Vec;
+use core::mem::size_of;
+
+/// Number of smallest encoded hashes retained by a `Kmv` sample
+pub(crate) const THETA_K: usize = 1024;
+
+/// Threshold of a sample holding every encoded hash: they all fit in 31 bits
+pub(crate) const COMPLETE: u32 = 1 << 31;
+
+/// Sorted, distinct smallest encoded hashes, at most `THETA_K` of them
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Kmv(Vec<u32>);
+
+impl Kmv {
+    /// Add an encoded hash, if it's among the `THETA_K` smallest
+    #[inline]
+    pub(crate) fn insert(&mut self, h: u32) {
+        if self.0.len() == THETA_K && h >= self.0[THETA_K - 1] {
+            return;
+        }
+        if let Err(pos) = self.0.binary_search(&h) {
+            if self.0.len() == THETA_K {
+                self.0.pop();
+            }
+            self.0.insert(pos, h);
+        }
+    }
+
+    /// Keep the `THETA_K` smallest encoded hashes of both samples
+    pub(crate) fn merge(&mut self, rhs: &Self) {
+        self.0.extend_from_slice(&rhs.0);
+        self.0.sort_unstable();
+        self.0.dedup();
+        self.0.truncate(THETA_K);
+    }
+
+    /// Sampled encoded hashes, in ascending order
+    pub(crate) fn hashes(&self) -> &[u32] {
+        &self.0
+    }
+
+    /// The sample holds every inserted encoded hash below this threshold
+    pub(crate) fn threshold(&self) -> u32 {
+        if self.0.len() == THETA_K {
+            self.0[THETA_K - 1]
+        } else {
+            COMPLETE
+        }
+    }
+
+    /// Heap-allocated bytes
+    pub(crate) fn heap_size(&self) -> usize {
+        self.0.capacity() * size_of::<u32>()
+    }
+}
+
+/// Estimate the size of the intersection of two sets from sorted samples that
+/// hold every encoded hash of their set below their threshold
+///
+/// Only hashes below both thresholds are compared, and their common count is
+/// scaled up by the sampled fraction of the hash space.
+pub(crate) fn intersection(lhs: (&[u32], u32), rhs: (&[u32], u32)) -> usize {
+    let threshold = lhs.1.min(rhs.1);
+    let (mut lhs, mut rhs) = (lhs.0.iter().peekable(), rhs.0.iter().peekable());
+    let mut common = 0;
+    while let (Some(&&l), Some(&&r)) = (lhs.peek(), rhs.peek()) {
+        if l >= threshold || r >= threshold {
+            break;
+        }
+        match l.cmp(&r) {
+            core::cmp::Ordering::Less => {
+                lhs.next();
+            }
+            core::cmp::Ordering::Greater => {
+                rhs.next();
+            }
+            core::cmp::Ordering::Equal => {
+                common += 1;
+                lhs.next();
+                rhs.next();
+            }
+        }
+    }
+    if threshold == COMPLETE {
+        common
+    } else {
+        (common as f64 * f64::from(COMPLETE) / f64::from(threshold) + 0.5) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kmv_keeps_smallest() {
+        let mut kmv = Kmv::default();
+        for h in (0..THETA_K as u32 * 4).rev() {
+            kmv.insert(h * 2);
+            kmv.insert(h * 2);
+        }
+        let expected: Vec<u32> = (0..THETA_K as u32).map(|h| h * 2).collect();
+        assert_eq!(kmv.hashes(), expected);
+        assert_eq!(kmv.threshold(), (THETA_K as u32 - 1) * 2);
+
+        let mut odd = Kmv::default();
+        for h in [5, 1, 3] {
+            odd.insert(h);
+        }
+        assert_eq!(odd.threshold(), COMPLETE);
+        odd.merge(&kmv);
+        assert_eq!(odd.hashes()[..5], [0, 1, 2, 3, 4]);
+        assert_eq!(odd.hashes().len(), THETA_K);
+    }
+
+    #[test]
+    fn test_intersection_complete() {
+        assert_eq!(
+            intersection((&[1, 2, 3], COMPLETE), (&[2, 3, 4], COMPLETE)),
+            2
+        );
+        // only hashes below both thresholds count, scaled up by the sampled fraction
+        assert_eq!(intersection((&[1, 2, 3], COMPLETE), (&[2, 3], 1 << 30)), 4);
+        assert_eq!(
+            intersection((&[1, 2, 3], 1 << 29), (&[2, 3, 1 << 29], COMPLETE)),
+            8
+        );
+    }
+}
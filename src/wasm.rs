@@ -0,0 +1,90 @@
+//! # JavaScript bindings
+//! With the `wasm` feature, `WasmSketch` wraps a default `Sketch` for use
+//! from JavaScript through `wasm-bindgen`, like deduplicating events in a
+//! browser before sending a sketch of them to a server.
+
+use crate::element::Element;
+use crate::serde::CompactSketch;
+use crate::sketch::{ParseError, Sketch};
+use alloc::vec::Vec;
+use wasm_bindgen::prelude::*;
+use wyhash::WyHash;
+
+/// A default `Sketch` exported to JavaScript
+///
+/// Strings are inserted as `Element::from_bytes_default::<WyHash>(s)`, the
+/// `WyHash` of their UTF-8 bytes, so that a server can insert the same
+/// elements with the `wyhash` crate. Serialized bytes are the `postcard`
+/// serialization of a `CompactSketch`, which `Sketch::merge_stream` and
+/// `Sketch::from_hex` (once hex encoded) also read.
+#[wasm_bindgen]
+#[derive(Debug, Clone, Default)]
+pub struct WasmSketch(Sketch);
+
+#[wasm_bindgen]
+impl WasmSketch {
+    /// Create an empty sketch
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Insert a string
+    pub fn insert_str(&mut self, s: &str) {
+        self.0.insert(Element::from_bytes_default::<WyHash>(s));
+    }
+
+    /// Estimate the number of distinct strings inserted, saturating at `u32::MAX`
+    pub fn estimate(&self) -> u32 {
+        u32::try_from(self.0.estimate()).unwrap_or(u32::MAX)
+    }
+
+    /// Serialize as the `postcard` serialization of a `CompactSketch`
+    pub fn serialize(&self) -> Vec<u8> {
+        postcard::to_allocvec(&CompactSketch::from(self.0.clone()))
+            .expect("serializing to a vec can't fail")
+    }
+
+    /// Merge a sketch serialized by `serialize` into this one
+    ///
+    /// Throws if the bytes don't deserialize, leaving this sketch unchanged.
+    pub fn merge_bytes(&mut self, bytes: &[u8]) -> Result<(), JsError> {
+        let rhs =
+            postcard::from_bytes::<CompactSketch>(bytes).map_err(|_| ParseError::InvalidSketch)?;
+        self.0.merge(&rhs.0);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::format;
+    use wasm_bindgen_test::{wasm_bindgen_test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    // `unsupported = test` also runs it as a native test, without a browser
+    #[wasm_bindgen_test(unsupported = test)]
+    fn test_wasm_sketch_round_trip() {
+        let events: Vec<_> = (0..1000).map(|i| format!("event-{i}")).collect();
+        let mut sketch = WasmSketch::new();
+        for event in events.iter().chain(&events[..100]) {
+            sketch.insert_str(event);
+        }
+        // the server inserts the same elements
+        let expected: Sketch = (events.iter())
+            .map(Element::from_bytes_default::<WyHash>)
+            .collect();
+        assert_eq!(sketch.0, expected);
+        assert_eq!(sketch.estimate() as usize, expected.estimate());
+
+        let bytes = sketch.serialize();
+        let mut merged = WasmSketch::new();
+        merged.merge_bytes(&bytes).unwrap();
+        assert_eq!(merged.0, expected);
+        assert_eq!(merged.estimate(), sketch.estimate());
+        let decoded = postcard::from_bytes::<CompactSketch>(&bytes).unwrap();
+        assert_eq!(Sketch::from(decoded), expected);
+    }
+}
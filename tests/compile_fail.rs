@@ -0,0 +1,8 @@
+//! Check that mixing sketch parameters is a compile-time error
+#![cfg(not(target_arch = "wasm32"))]
+
+#[test]
+fn compile_fail() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/*.rs");
+}
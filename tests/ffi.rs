@@ -0,0 +1,82 @@
+//! Drive the C bindings like a C caller would
+#![cfg(feature = "ffi")]
+
+use cardinality_estimator_safe::ffi::{
+    ce_bytes_free, ce_estimate, ce_free, ce_insert_hash, ce_merge, ce_new, ce_serialize,
+};
+use cardinality_estimator_safe::{CompactSketch, Element, Sketch};
+use core::hash::BuildHasher;
+
+fn hash(i: u64) -> u64 {
+    core::hash::BuildHasherDefault::<wyhash::WyHash>::default().hash_one(i)
+}
+
+fn serialized(sketch: &cardinality_estimator_safe::ffi::CeSketch) -> Vec<u8> {
+    let mut len = 0;
+    let ptr = ce_serialize(Some(sketch), Some(&mut len));
+    assert!(!ptr.is_null());
+    // SAFETY: `ce_serialize` returned `len` bytes owned by the caller, which
+    // are copied before being released once
+    let bytes = unsafe { core::slice::from_raw_parts(ptr, len) }.to_vec();
+    unsafe { ce_bytes_free(ptr, len) };
+    bytes
+}
+
+#[test]
+fn test_ffi() {
+    let mut a = ce_new();
+    let mut b = ce_new();
+    for i in 0..1000 {
+        ce_insert_hash(Some(&mut a), hash(i));
+    }
+    for i in 500..2000 {
+        ce_insert_hash(Some(&mut b), hash(i));
+    }
+    // the same sketches through the Rust API
+    let mut expected: Sketch = (0..1000).map(|i| Element::from_hashed(hash(i))).collect();
+    let expected_b: Sketch = (500..2000).map(|i| Element::from_hashed(hash(i))).collect();
+    assert_eq!(ce_estimate(Some(&a)), expected.estimate() as u64);
+
+    ce_merge(Some(&mut a), Some(&b));
+    expected.merge(&expected_b);
+    assert_eq!(ce_estimate(Some(&a)), expected.estimate() as u64);
+    assert!(expected.approx_eq(&Sketch::from_hashes((0..2000).map(hash)), 0.05));
+
+    let bytes = serialized(&a);
+    let decoded: Sketch = postcard::from_bytes::<CompactSketch>(&bytes)
+        .unwrap()
+        .into();
+    assert_eq!(decoded, expected);
+
+    // the bytes outlive the sketch they were serialized from
+    let mut len = 0;
+    let ptr = ce_serialize(Some(&b), Some(&mut len));
+    ce_free(Some(b));
+    // SAFETY: `ce_serialize` returned `len` bytes owned by the caller, which
+    // are released once after use
+    let decoded: CompactSketch =
+        postcard::from_bytes(unsafe { core::slice::from_raw_parts(ptr, len) }).unwrap();
+    unsafe { ce_bytes_free(ptr, len) };
+    assert_eq!(decoded.0, expected_b);
+
+    ce_free(Some(a));
+}
+
+#[test]
+fn test_ffi_null() {
+    ce_insert_hash(None, hash(0));
+    assert_eq!(ce_estimate(None), 0);
+    ce_merge(None, None);
+    let mut len = 1;
+    assert!(ce_serialize(None, Some(&mut len)).is_null());
+    assert_eq!(len, 0);
+    // SAFETY: freeing null bytes does nothing
+    unsafe { ce_bytes_free(core::ptr::null_mut(), 0) };
+    ce_free(None);
+
+    let mut a = ce_new();
+    ce_insert_hash(Some(&mut a), hash(0));
+    ce_merge(Some(&mut a), None);
+    assert_eq!(ce_estimate(Some(&a)), 1);
+    ce_free(Some(a));
+}
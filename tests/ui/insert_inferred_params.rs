@@ -0,0 +1,12 @@
+use cardinality_estimator_safe::{Element, Sketch};
+
+fn main() {
+    // the element's parameters are inferred from its first use...
+    let element = Element::from_hashed(0x1234_5678_9abc_def0);
+    let mut lhs = Sketch::<10, 5>::default();
+    lhs.insert(element);
+
+    // ...so it can't be inserted into a sketch with other parameters
+    let mut rhs = Sketch::<12, 6>::default();
+    rhs.insert(element);
+}
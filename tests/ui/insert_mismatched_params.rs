@@ -0,0 +1,7 @@
+use cardinality_estimator_safe::{Element, Sketch};
+
+fn main() {
+    let element: Element<12, 6> = Element::from_hashed(0x1234_5678_9abc_def0);
+    let mut sketch = Sketch::<10, 5>::default();
+    sketch.insert(element);
+}
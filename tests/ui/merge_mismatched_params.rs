@@ -0,0 +1,7 @@
+use cardinality_estimator_safe::Sketch;
+
+fn main() {
+    let mut lhs = Sketch::<12, 6>::default();
+    let rhs = Sketch::<10, 6>::default();
+    lhs.merge(&rhs);
+}